@@ -0,0 +1,3 @@
+UPDATE transactions
+SET input = 'Bytes(' || input || ')'
+WHERE input NOT LIKE 'Bytes(%)';
@@ -0,0 +1,5 @@
+-- `input` used to be stored with the `Debug` format of `ethers::types::Bytes`,
+-- i.e. `Bytes(0x...)`, instead of the plain hex string
+UPDATE transactions
+SET input = substring(input FROM 7 FOR length(input) - 7)
+WHERE input LIKE 'Bytes(%)';
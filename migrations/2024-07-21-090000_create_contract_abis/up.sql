@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS contract_abis (
+    address TEXT PRIMARY KEY,
+    abi TEXT NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS transaction_logs (
+    transaction_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    topic0 TEXT,
+    topic1 TEXT,
+    topic2 TEXT,
+    topic3 TEXT,
+    data TEXT NOT NULL,
+    PRIMARY KEY (transaction_hash, log_index),
+    FOREIGN KEY (transaction_hash) REFERENCES transactions(transaction_hash)
+);
+
+CREATE INDEX IF NOT EXISTS transaction_logs_address_topic0_block_idx
+    ON transaction_logs (address, topic0, block_number);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS token_transfers (
+    transaction_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    token TEXT NOT NULL,
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (transaction_hash, log_index),
+    FOREIGN KEY (transaction_hash) REFERENCES transactions(transaction_hash)
+);
+
+CREATE INDEX IF NOT EXISTS token_transfers_from_address_idx ON token_transfers (from_address);
+CREATE INDEX IF NOT EXISTS token_transfers_to_address_idx ON token_transfers (to_address);
+
+CREATE TABLE IF NOT EXISTS token_metadata (
+    token TEXT PRIMARY KEY,
+    name TEXT,
+    symbol TEXT,
+    decimals INTEGER
+);
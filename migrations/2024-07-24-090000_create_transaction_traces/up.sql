@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS transaction_traces (
+    transaction_hash TEXT NOT NULL,
+    trace_address TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    call_type TEXT NOT NULL,
+    from_address TEXT NOT NULL,
+    to_address TEXT,
+    value TEXT NOT NULL,
+    gas BIGINT NOT NULL,
+    gas_used BIGINT NOT NULL,
+    input TEXT NOT NULL,
+    output TEXT,
+    error TEXT,
+    PRIMARY KEY (transaction_hash, trace_address),
+    FOREIGN KEY (transaction_hash) REFERENCES transactions(transaction_hash)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS blocks (
+    hash TEXT PRIMARY KEY,
+    number BIGINT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    miner TEXT NOT NULL,
+    gas_used BIGINT NOT NULL,
+    gas_limit BIGINT NOT NULL,
+    base_fee_per_gas TEXT,
+    transaction_count BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS blocks_number_idx ON blocks (number);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS transaction_fees (
+    transaction_hash TEXT PRIMARY KEY,
+    gas_used BIGINT NOT NULL,
+    effective_gas_price TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    FOREIGN KEY (transaction_hash) REFERENCES transactions(transaction_hash)
+);
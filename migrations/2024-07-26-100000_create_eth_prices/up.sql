@@ -0,0 +1,7 @@
+-- Daily ETH prices, `day` is an ISO 8601 date (UTC) and `price` a decimal string
+CREATE TABLE IF NOT EXISTS eth_prices (
+    currency TEXT NOT NULL,
+    day TEXT NOT NULL,
+    price TEXT NOT NULL,
+    PRIMARY KEY (currency, day)
+);
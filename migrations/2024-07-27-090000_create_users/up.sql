@@ -0,0 +1,5 @@
+-- Users allowed to authenticate, `password_hash` is an Argon2 PHC string
+CREATE TABLE IF NOT EXISTS users (
+    username TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE users_searches DROP CONSTRAINT IF EXISTS users_searches_username_transaction_hash_key;
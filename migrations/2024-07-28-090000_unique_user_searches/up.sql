@@ -0,0 +1,10 @@
+-- Keep the first of any duplicate searches so the constraint can be added
+DELETE FROM users_searches duplicate
+USING users_searches original
+WHERE duplicate.username = original.username
+    AND duplicate.transaction_hash = original.transaction_hash
+    AND duplicate.id > original.id;
+
+-- Lets concurrent lookups record searches with `ON CONFLICT DO NOTHING`
+ALTER TABLE users_searches
+    ADD CONSTRAINT users_searches_username_transaction_hash_key UNIQUE (username, transaction_hash);
@@ -0,0 +1,8 @@
+-- Hashes the ETH node didn't know, so they aren't fetched again until
+-- `checked_at` (unix seconds) is older than `cache.negative_ttl_secs`
+CREATE TABLE IF NOT EXISTS unknown_transactions (
+    transaction_hash TEXT PRIMARY KEY,
+    checked_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS unknown_transactions_checked_at_idx ON unknown_transactions (checked_at);
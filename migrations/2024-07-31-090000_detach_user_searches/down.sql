@@ -0,0 +1,6 @@
+-- Searches of purged transactions can't be kept under the constraint
+DELETE FROM users_searches
+WHERE transaction_hash NOT IN (SELECT transaction_hash FROM transactions);
+
+ALTER TABLE users_searches
+    ADD CONSTRAINT users_searches_transaction_hash_fkey FOREIGN KEY (transaction_hash) REFERENCES transactions(transaction_hash);
@@ -0,0 +1,4 @@
+-- Searches are user history, transactions only a cache of the node: purging
+-- the cache must not take the history with it. A purged transaction shows up
+-- in `/lime/my` again once it is fetched again.
+ALTER TABLE users_searches DROP CONSTRAINT IF EXISTS users_searches_transaction_hash_fkey;
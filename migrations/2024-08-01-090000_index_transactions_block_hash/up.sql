@@ -0,0 +1,2 @@
+-- Blocks list their transactions by hash, see `get_block_transactions_from_db`
+CREATE INDEX IF NOT EXISTS transactions_block_hash_idx ON transactions (block_hash);
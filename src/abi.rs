@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use actix_web::{web, post, delete, Responder, HttpResponse, HttpRequest};
+use serde::Serialize;
+use serde_json::Value;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+use ethers::abi::{Abi, Function, Token, parse_abi};
+use ethers::types::{Address, I256};
+use ethers::utils::hex;
+use tracing::error;
+
+use crate::DBPool;
+use crate::db::with_conn;
+use crate::auth::require_admin;
+use crate::schema::contract_abis;
+
+/// Human readable signatures of widely used contracts, used to decode calldata
+/// of contracts without a registered ABI
+const KNOWN_SIGNATURES: &[&str] = &[
+    // ERC-20
+    "function transfer(address to, uint256 amount) returns (bool)",
+    "function transferFrom(address from, address to, uint256 amount) returns (bool)",
+    "function approve(address spender, uint256 amount) returns (bool)",
+    "function increaseAllowance(address spender, uint256 addedValue) returns (bool)",
+    "function decreaseAllowance(address spender, uint256 subtractedValue) returns (bool)",
+    // ERC-721 / ERC-1155
+    "function safeTransferFrom(address from, address to, uint256 tokenId)",
+    "function safeTransferFrom(address from, address to, uint256 tokenId, bytes data)",
+    "function safeTransferFrom(address from, address to, uint256 id, uint256 amount, bytes data)",
+    "function safeBatchTransferFrom(address from, address to, uint256[] ids, uint256[] amounts, bytes data)",
+    "function setApprovalForAll(address operator, bool approved)",
+    "function mint(address to, uint256 amount)",
+    "function burn(uint256 amount)",
+    // WETH
+    "function deposit()",
+    "function withdraw(uint256 amount)",
+    // Uniswap V2 style routers
+    "function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) returns (uint256[])",
+    "function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline) returns (uint256[])",
+    "function swapExactETHForTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline) returns (uint256[])",
+    "function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline) returns (uint256[])",
+    // Multicall / Safe
+    "function multicall(bytes[] data) returns (bytes[])",
+    "function execTransaction(address to, uint256 value, bytes data, uint8 operation, uint256 safeTxGas, uint256 baseGas, uint256 gasPrice, address gasToken, address refundReceiver, bytes signatures) returns (bool)",
+];
+
+/// ABI registered for a single contract, stored as its JSON representation
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = contract_abis)]
+pub struct ContractAbi {
+    pub address: String,
+    pub abi: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DecodeSource {
+    /// ABI registered for the contract
+    Abi,
+    /// Bundled table of well known selectors
+    Selector,
+    /// Bundled token standard events
+    Standard,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedArgument {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: Value,
+}
+
+/// Function call decoded out of `Transaction.input`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedCall {
+    pub selector: String,
+    pub function: String,
+    pub signature: String,
+    pub arguments: Vec<DecodedArgument>,
+    pub source: DecodeSource,
+}
+
+fn known_signatures() -> &'static Abi {
+    static KNOWN_ABI: OnceLock<Abi> = OnceLock::new();
+    KNOWN_ABI.get_or_init(|| parse_abi(KNOWN_SIGNATURES).expect("bundled signatures are valid"))
+}
+
+/// Decodes calldata with the contract ABI if there is one, falling back to the
+/// bundled selectors. Anything that doesn't decode cleanly just yields `None`.
+pub fn decode_input(abi: Option<&Abi>, input: &str) -> Option<DecodedCall> {
+    let data = hex::decode(input).ok()?;
+    if data.len() < 4 {
+        return None;
+    }
+
+    abi.and_then(|abi| decode_with_abi(abi, &data, DecodeSource::Abi))
+        .or_else(|| decode_with_abi(known_signatures(), &data, DecodeSource::Selector))
+}
+
+fn decode_with_abi(abi: &Abi, data: &[u8], source: DecodeSource) -> Option<DecodedCall> {
+    let function = abi.functions().find(|function| function.short_signature() == data[..4])?;
+    let tokens = function.decode_input(&data[4..]).ok()?;
+
+    Some(DecodedCall {
+        selector: format!("0x{}", hex::encode(&data[..4])),
+        function: function.name.clone(),
+        signature: function_signature(function),
+        arguments: decoded_arguments(function, tokens),
+        source,
+    })
+}
+
+/// `name(type,...)`, `Function::signature` also appends the outputs
+fn function_signature(function: &Function) -> String {
+    let inputs: Vec<String> = function.inputs.iter().map(|param| param.kind.to_string()).collect();
+    format!("{}({})", function.name, inputs.join(","))
+}
+
+fn decoded_arguments(function: &Function, tokens: Vec<Token>) -> Vec<DecodedArgument> {
+    function.inputs
+        .iter()
+        .zip(tokens)
+        .map(|(param, token)| DecodedArgument {
+            name: param.name.clone(),
+            kind: param.kind.to_string(),
+            value: token_to_json(&token),
+        })
+        .collect()
+}
+
+/// JSON representation of an ABI value: numbers as decimal strings (they don't fit
+/// in a JSON number), addresses and bytes as hex, arrays and tuples as arrays
+pub fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => Value::String(format!("{:?}", address)),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => Value::String(format!("0x{}", hex::encode(bytes))),
+        Token::Int(int) => Value::String(I256::from_raw(*int).to_string()),
+        Token::Uint(uint) => Value::String(uint.to_string()),
+        Token::Bool(b) => Value::Bool(*b),
+        Token::String(s) => Value::String(s.clone()),
+        Token::FixedArray(tokens) | Token::Array(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_to_json).collect())
+        }
+    }
+}
+
+pub async fn store_contract_abi(pool: &DBPool, contract_abi: &ContractAbi) -> Result<(), diesel::result::Error> {
+    use crate::schema::contract_abis::dsl::*;
+
+    let contract_abi = contract_abi.clone();
+    with_conn(pool, move |conn| {
+        diesel::insert_into(contract_abis)
+            .values(&contract_abi)
+            .on_conflict(address)
+            .do_update()
+            .set(abi.eq(excluded(abi)))
+            .execute(conn)?;
+        Ok(())
+    }).await
+}
+
+pub async fn delete_contract_abi(pool: &DBPool, contract_address: &str) -> Result<usize, diesel::result::Error> {
+    use crate::schema::contract_abis::dsl::*;
+
+    let contract_address = contract_address.to_string();
+    with_conn(pool, move |conn| diesel::delete(contract_abis.filter(address.eq(contract_address))).execute(conn)).await
+}
+
+/// Registered ABIs of the given contracts, keyed by address. ABIs that no longer parse are skipped.
+pub async fn get_contract_abis(pool: &DBPool, contract_addresses: &[String]) -> Result<HashMap<String, Abi>, diesel::result::Error> {
+    use crate::schema::contract_abis::dsl::*;
+
+    let contract_addresses = contract_addresses.to_vec();
+    let stored = with_conn(pool, move |conn| {
+        contract_abis
+            .filter(address.eq_any(contract_addresses))
+            .load::<ContractAbi>(conn)
+    }).await?;
+
+    Ok(stored
+        .into_iter()
+        .filter_map(|contract_abi| {
+            let parsed = serde_json::from_str::<Abi>(&contract_abi.abi).ok()?;
+            Some((contract_abi.address, parsed))
+        })
+        .collect())
+}
+
+fn parse_contract_address(address: &str) -> Option<String> {
+    Address::from_str(address).ok().map(|address| format!("{:?}", address))
+}
+
+/// Registers (or replaces) the JSON ABI of a contract, admin only
+#[post("/lime/admin/abi/{address}")]
+pub async fn register_abi(path: web::Path<String>, body: web::Json<Value>, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let Some(address) = parse_contract_address(&path.into_inner()) else {
+        return HttpResponse::BadRequest().body("Invalid Contract Address");
+    };
+
+    let abi_json = body.into_inner();
+    if serde_json::from_value::<Abi>(abi_json.clone()).is_err() {
+        return HttpResponse::BadRequest().body("Invalid ABI");
+    }
+
+    let contract_abi = ContractAbi { address, abi: abi_json.to_string() };
+    match store_contract_abi(&pool, &contract_abi).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "address": contract_abi.address })),
+        Err(e) => {
+            error!(error = %e, "Failed to save contract ABI");
+            HttpResponse::InternalServerError().body("Failed to save contract ABI")
+        }
+    }
+}
+
+#[delete("/lime/admin/abi/{address}")]
+pub async fn unregister_abi(path: web::Path<String>, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let Some(address) = parse_contract_address(&path.into_inner()) else {
+        return HttpResponse::BadRequest().body("Invalid Contract Address");
+    };
+
+    match delete_contract_abi(&pool, &address).await {
+        Ok(0) => HttpResponse::NotFound().body("No ABI registered for this contract"),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            error!(error = %e, "Failed to delete contract ABI");
+            HttpResponse::InternalServerError().body("Failed to delete contract ABI")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // transfer(0x2222222222222222222222222222222222222222, 1000)
+    const TRANSFER_INPUT: &str = "0xa9059cbb000000000000000000000000222222222222222222222222222222222222222200000000000000000000000000000000000000000000000000000000000003e8";
+
+    #[test]
+    fn test_decode_input_known_selector() {
+        let decoded = decode_input(None, TRANSFER_INPUT).unwrap();
+        assert_eq!(decoded.function, "transfer");
+        assert_eq!(decoded.signature, "transfer(address,uint256)");
+        assert_eq!(decoded.source, DecodeSource::Selector);
+        assert_eq!(decoded.arguments[0].value, Value::String("0x2222222222222222222222222222222222222222".into()));
+        assert_eq!(decoded.arguments[1].kind, "uint256");
+        assert_eq!(decoded.arguments[1].value, Value::String("1000".into()));
+    }
+
+    #[test]
+    fn test_decode_input_registered_abi() {
+        let abi = parse_abi(&["function transfer(address recipient, uint256 wad)"]).unwrap();
+        let decoded = decode_input(Some(&abi), TRANSFER_INPUT).unwrap();
+        assert_eq!(decoded.source, DecodeSource::Abi);
+        assert_eq!(decoded.arguments[0].name, "recipient");
+    }
+
+    #[test]
+    fn test_decode_input_undecodable() {
+        assert_eq!(decode_input(None, "0x"), None);
+        assert_eq!(decode_input(None, "0xdeadbeef"), None);
+        assert_eq!(decode_input(None, "not hex"), None);
+        // Known selector but truncated arguments
+        assert_eq!(decode_input(None, "0xa9059cbb0000"), None);
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use actix_web::{web, get, HttpRequest, Responder, HttpResponse};
+use futures_util::stream;
+use serde::Deserialize;
+
+use arrow::array::{ArrayRef, BinaryArray, BooleanArray, Decimal256Array, FixedSizeBinaryArray, Int64Array, TimestampSecondArray};
+use arrow::datatypes::{i256, DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+
+use ethers::types::{Address, U256};
+use tracing::error;
+
+use crate::DBPool;
+use crate::auth::require_admin;
+use crate::db::with_conn;
+use crate::blocks::get_block_timestamps;
+use crate::transaction::{Transaction, TransactionFilter, get_filtered_transactions_page_from_db, hex_to_bytes};
+
+pub const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+
+/// Rows read from Postgres and written per row group
+const PARQUET_CHUNK_SIZE: i64 = 10_000;
+
+/// Largest precision of a `Decimal256`, enough for any amount of wei in circulation
+const WEI_PRECISION: u8 = 76;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Db(diesel::result::Error),
+    Arrow(ArrowError),
+    Parquet(ParquetError),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Db(e) => write!(f, "Database error: {}", e),
+            ExportError::Arrow(e) => write!(f, "Arrow error: {}", e),
+            ExportError::Parquet(e) => write!(f, "Parquet error: {}", e),
+            ExportError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ExportError {
+    fn from(e: diesel::result::Error) -> Self {
+        ExportError::Db(e)
+    }
+}
+
+impl From<ArrowError> for ExportError {
+    fn from(e: ArrowError) -> Self {
+        ExportError::Arrow(e)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+/// Arrow schema of the exported `transactions` table: hashes and addresses as
+/// raw bytes, `value` as a decimal number of wei and the block time as a timestamp
+pub fn transactions_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("transaction_hash", DataType::FixedSizeBinary(32), false),
+        Field::new("transaction_status", DataType::Boolean, false),
+        Field::new("block_hash", DataType::FixedSizeBinary(32), false),
+        Field::new("block_number", DataType::Int64, false),
+        Field::new("block_timestamp", DataType::Timestamp(TimeUnit::Second, Some("UTC".into())), true),
+        Field::new("from", DataType::FixedSizeBinary(20), false),
+        Field::new("to", DataType::FixedSizeBinary(20), true),
+        Field::new("contract_address", DataType::FixedSizeBinary(20), true),
+        Field::new("logs_count", DataType::Int64, false),
+        Field::new("input", DataType::Binary, false),
+        Field::new("value", DataType::Decimal256(WEI_PRECISION, 0), false),
+    ]))
+}
+
+fn fixed_size_binary<'a>(values: impl Iterator<Item = Option<&'a str>>, size: i32) -> Result<FixedSizeBinaryArray, ArrowError> {
+    let bytes = values.map(|value| value.map(hex_to_bytes).filter(|bytes| bytes.len() == size as usize));
+    FixedSizeBinaryArray::try_from_sparse_iter_with_size(bytes, size)
+}
+
+fn wei_to_i256(wei: &str) -> i256 {
+    let mut bytes = [0u8; 32];
+    U256::from_dec_str(wei).unwrap_or_default().to_big_endian(&mut bytes);
+    i256::from_be_bytes(bytes)
+}
+
+/// One record batch out of a chunk of transactions, `timestamps` keyed by block hash
+pub fn transactions_record_batch(transactions: &[Transaction], timestamps: &HashMap<String, i64>) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(fixed_size_binary(transactions.iter().map(|tx| Some(tx.transaction_hash.as_str())), 32)?),
+        Arc::new(BooleanArray::from_iter(transactions.iter().map(|tx| Some(tx.transaction_status)))),
+        Arc::new(fixed_size_binary(transactions.iter().map(|tx| Some(tx.block_hash.as_str())), 32)?),
+        Arc::new(Int64Array::from_iter_values(transactions.iter().map(|tx| tx.block_number))),
+        Arc::new(TimestampSecondArray::from_iter(transactions.iter().map(|tx| timestamps.get(&tx.block_hash).copied())).with_timezone("UTC")),
+        Arc::new(fixed_size_binary(transactions.iter().map(|tx| Some(tx.from.as_str())), 20)?),
+        Arc::new(fixed_size_binary(transactions.iter().map(|tx| tx.to.as_deref()), 20)?),
+        Arc::new(fixed_size_binary(transactions.iter().map(|tx| tx.contract_address.as_deref()), 20)?),
+        Arc::new(Int64Array::from_iter_values(transactions.iter().map(|tx| tx.logs_count))),
+        Arc::new(BinaryArray::from_iter_values(transactions.iter().map(|tx| hex_to_bytes(&tx.input)))),
+        Arc::new(Decimal256Array::from_iter_values(transactions.iter().map(|tx| wei_to_i256(&tx.value))).with_precision_and_scale(WEI_PRECISION, 0)?),
+    ];
+    RecordBatch::try_new(transactions_schema(), columns)
+}
+
+fn parquet_writer<W: Write + Send>(writer: W) -> Result<ArrowWriter<W>, ExportError> {
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    Ok(ArrowWriter::try_new(writer, transactions_schema(), Some(properties))?)
+}
+
+/// Writes the next chunk of transactions matching `filter` (ordered by hash,
+/// after `after`) as one row group. Returns the rows written and the hash to
+/// continue after, `None` once there are no more chunks to read.
+async fn write_row_group<W: Write + Send>(pool: &DBPool, filter: &TransactionFilter, writer: &mut ArrowWriter<W>, after: Option<&str>) -> Result<(usize, Option<String>), ExportError> {
+    let chunk = get_filtered_transactions_page_from_db(pool, filter, after, PARQUET_CHUNK_SIZE).await?;
+    if chunk.is_empty() {
+        return Ok((0, None));
+    }
+
+    let block_hashes: Vec<String> = chunk.iter().map(|tx| tx.block_hash.clone()).collect();
+    let timestamps = get_block_timestamps(pool, &block_hashes).await?;
+    writer.write(&transactions_record_batch(&chunk, &timestamps)?)?;
+    writer.flush()?;
+
+    let last = match chunk.len() < PARQUET_CHUNK_SIZE as usize {
+        true => None,
+        false => chunk.last().map(|tx| tx.transaction_hash.clone()),
+    };
+    Ok((chunk.len(), last))
+}
+
+/// Writes the transactions matching `filter` as Parquet, one row group per chunk
+/// read from the database. Returns the number of rows written.
+pub async fn write_transactions_parquet<W: Write + Send>(pool: &DBPool, filter: &TransactionFilter, writer: W) -> Result<usize, ExportError> {
+    let mut writer = parquet_writer(writer)?;
+
+    let mut rows = 0;
+    let mut after: Option<String> = None;
+    loop {
+        let (written, last) = write_row_group(pool, filter, &mut writer, after.as_deref()).await?;
+        rows += written;
+        match last {
+            Some(last) => after = Some(last),
+            None => break,
+        }
+    }
+
+    writer.close()?;
+    Ok(rows)
+}
+
+/// Block ranges of `partition_size` blocks covering `from_block..=to_block`,
+/// aligned on multiples of `partition_size` so partitions are stable across exports
+pub fn block_partitions(from_block: i64, to_block: i64, partition_size: i64) -> Vec<(i64, i64)> {
+    let start = from_block - from_block.rem_euclid(partition_size);
+    (start..=to_block)
+        .step_by(partition_size as usize)
+        .map(|partition_start| (partition_start, partition_start + partition_size - 1))
+        .collect()
+}
+
+async fn block_range(pool: &DBPool, filter: &TransactionFilter) -> Result<Option<(i64, i64)>, diesel::result::Error> {
+    use diesel::dsl::{max, min};
+    use diesel::prelude::*;
+    use crate::schema::transactions::dsl::*;
+
+    let (lowest, highest) = with_conn(pool, |conn| {
+        transactions
+            .select((min(block_number), max(block_number)))
+            .first::<(Option<i64>, Option<i64>)>(conn)
+    }).await?;
+
+    Ok(match (lowest, highest) {
+        (Some(lowest), Some(highest)) => Some((filter.from_block.unwrap_or(lowest).max(lowest), filter.to_block.unwrap_or(highest).min(highest))),
+        _ => None,
+    })
+}
+
+/// Exports into `dir`, as a single `transactions.parquet` or with
+/// `partition_size` as one `transactions_{from}_{to}.parquet` file per block
+/// range. Partitions without transactions are skipped. Returns the files written.
+pub async fn export_transactions_parquet(pool: &DBPool, filter: &TransactionFilter, dir: &Path, partition_size: Option<i64>) -> Result<Vec<(PathBuf, usize)>, ExportError> {
+    fs::create_dir_all(dir)?;
+
+    let Some(partition_size) = partition_size.filter(|size| *size > 0) else {
+        let path = dir.join("transactions.parquet");
+        let rows = write_transactions_parquet(pool, filter, File::create(&path)?).await?;
+        return Ok(vec![(path, rows)]);
+    };
+
+    let Some((from_block, to_block)) = block_range(pool, filter).await? else {
+        return Ok(Vec::new());
+    };
+
+    let mut files = Vec::new();
+    for (partition_from, partition_to) in block_partitions(from_block, to_block, partition_size) {
+        let partition = TransactionFilter {
+            from_block: Some(partition_from.max(from_block)),
+            to_block: Some(partition_to.min(to_block)),
+            ..filter.clone()
+        };
+        if get_filtered_transactions_page_from_db(pool, &partition, None, 1).await?.is_empty() {
+            continue;
+        }
+
+        let path = dir.join(format!("transactions_{}_{}.parquet", partition_from, partition_to));
+        let rows = write_transactions_parquet(pool, &partition, File::create(&path)?).await?;
+        files.push((path, rows));
+    }
+    Ok(files)
+}
+
+/// Query parameters of `/lime/export/parquet`, also used by the `export-parquet` command
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportParams {
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    pub address: Option<String>,
+}
+
+impl ExportParams {
+    pub fn filter(&self) -> Result<TransactionFilter, String> {
+        let address = match &self.address {
+            Some(address) => Some(Address::from_str(address).map(|address| format!("{:?}", address)).map_err(|_| "Invalid Address".to_string())?),
+            None => None,
+        };
+        Ok(TransactionFilter { from_block: self.from_block, to_block: self.to_block, address })
+    }
+}
+
+/// Bytes written by the Parquet writer that weren't sent yet
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> web::Bytes {
+        web::Bytes::from(std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner)))
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct ParquetExportState {
+    pool: DBPool,
+    filter: TransactionFilter,
+    buffer: SharedBuffer,
+    /// `None` once the file is complete
+    writer: Option<ArrowWriter<SharedBuffer>>,
+    after: Option<String>,
+}
+
+impl ParquetExportState {
+    /// Bytes of the next row group, or of the footer once every row is written
+    async fn next_bytes(&mut self) -> Result<Option<web::Bytes>, ExportError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(None);
+        };
+        let (_, last) = write_row_group(&self.pool, &self.filter, writer, self.after.as_deref()).await?;
+        match last {
+            Some(last) => self.after = Some(last),
+            None => {
+                if let Some(writer) = self.writer.take() {
+                    writer.close()?;
+                }
+            }
+        }
+        Ok(Some(self.buffer.take()))
+    }
+}
+
+/// Single Parquet file with the matching transactions, admin only. Streamed
+/// one row group at a time, so memory use doesn't grow with the table.
+#[get("/lime/export/parquet")]
+pub async fn lime_export_parquet(params: web::Query<ExportParams>, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let filter = match params.filter() {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let buffer = SharedBuffer::default();
+    let writer = match parquet_writer(buffer.clone()) {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!(error = %e, "Failed to export transactions");
+            return HttpResponse::InternalServerError().body("Failed to export transactions");
+        }
+    };
+    let state = ParquetExportState { pool: pool.get_ref().clone(), filter, buffer, writer: Some(writer), after: None };
+
+    let body = stream::unfold(state, |mut state| async move {
+        match state.next_bytes().await {
+            Ok(Some(bytes)) => Some((Ok(bytes), state)),
+            Ok(None) => None,
+            Err(e) => {
+                error!(error = %e, "Failed to export transactions");
+                // Nothing more to send, the client gets a truncated file
+                state.writer = None;
+                Some((Err(actix_web::error::ErrorInternalServerError("Failed to export transactions")), state))
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(PARQUET_CONTENT_TYPE)
+        .insert_header(("Content-Disposition", "attachment; filename=\"transactions.parquet\""))
+        .streaming(body)
+}
+
+/// `export-parquet --out <dir> [--from-block <n>] [--to-block <n>] [--address <address>] [--partition-size <blocks>]`
+pub async fn run_export_command(pool: &DBPool, args: &[String]) -> Result<(), String> {
+    let mut params = ExportParams::default();
+    let mut out: Option<PathBuf> = None;
+    let mut partition_size: Option<i64> = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", flag))?;
+        let number = || value.parse::<i64>().map_err(|_| format!("Invalid number for {}: {}", flag, value));
+        match flag.as_str() {
+            "--out" => out = Some(PathBuf::from(value)),
+            "--from-block" => params.from_block = Some(number()?),
+            "--to-block" => params.to_block = Some(number()?),
+            "--address" => params.address = Some(value.clone()),
+            "--partition-size" => partition_size = Some(number()?),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    let out = out.ok_or("Missing --out <dir>")?;
+    let filter = params.filter()?;
+    let files = export_transactions_parquet(pool, &filter, &out, partition_size).await.map_err(|e| e.to_string())?;
+    for (path, rows) in files {
+        println!("{}: {} transactions", path.display(), rows);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_transaction;
+
+    use arrow::array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn transaction(value: &str, to: Option<&str>) -> Transaction {
+        Transaction {
+            to: to.map(str::to_string),
+            logs_count: 1,
+            input: "0xa9059cbb".to_string(),
+            value: value.to_string(),
+            ..test_transaction("0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e", "0xabababababababababababababababababababababababababababababababab", 5703601)
+        }
+    }
+
+    #[test]
+    fn test_transactions_record_batch() {
+        let transactions = vec![
+            transaction("1000000000000000000", Some("0x2222222222222222222222222222222222222222")),
+            transaction("0", None),
+        ];
+        let timestamps = HashMap::from([(transactions[0].block_hash.clone(), 1719726496)]);
+        let batch = transactions_record_batch(&transactions, &timestamps).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+
+        // Round trip through a Parquet file keeps the types
+        let mut buffer = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buffer, transactions_schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(web::Bytes::from(buffer)).unwrap().build().unwrap();
+        let read: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(read[0].schema(), transactions_schema());
+
+        let hashes = read[0].column(0).as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+        assert_eq!(hashes.value(0), hex_to_bytes(&transactions[0].transaction_hash).as_slice());
+        let timestamps = read[0].column(4).as_any().downcast_ref::<TimestampSecondArray>().unwrap();
+        assert_eq!(timestamps.value(0), 1719726496);
+        let to = read[0].column(6).as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+        assert!(to.is_null(1));
+        let values = read[0].column(10).as_any().downcast_ref::<Decimal256Array>().unwrap();
+        assert_eq!(values.value_as_string(0), "1000000000000000000");
+    }
+
+    #[test]
+    fn test_block_partitions() {
+        assert_eq!(block_partitions(5, 25, 10), vec![(0, 9), (10, 19), (20, 29)]);
+        assert_eq!(block_partitions(10, 19, 10), vec![(10, 19)]);
+        assert_eq!(block_partitions(7, 7, 1000), vec![(0, 999)]);
+    }
+
+    #[test]
+    fn test_export_params_filter() {
+        let params = ExportParams { from_block: Some(1), to_block: None, address: Some("0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string()) };
+        let filter = params.filter().unwrap();
+        assert_eq!(filter.address.as_deref(), Some("0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+
+        let params = ExportParams { address: Some("0x12".to_string()), ..Default::default() };
+        assert!(params.filter().is_err());
+    }
+}
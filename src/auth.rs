@@ -1,12 +1,13 @@
-use actix_web::{post, web, HttpResponse, Responder};
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use jsonwebtoken::{encode, Header, EncodingKey, decode, DecodingKey, Validation};
 use jsonwebtoken::errors::Result as JwtResult;
 use chrono::{Utc, Duration};
-use std::env;
+use tracing::error;
 
-// around 10 seconds (for testing purposes)
-pub const TOKEN_EXPIRATION: usize = 10;
+use crate::DBPool;
+use crate::config::Config;
+use crate::users::{get_user_from_token, verify_credentials};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthData {
@@ -38,34 +39,50 @@ struct TokenResponse {
     token: String,
 }
 
-fn create_jwt(username: &str) -> JwtResult<String> {
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "mysecret".to_string());
-    let exp = TOKEN_EXPIRATION;
-    let claims = Claims::new(username, exp);
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref()))
+fn create_jwt(config: &Config, username: &str) -> JwtResult<String> {
+    let claims = Claims::new(username, config.auth.token_lifetime_secs as usize);
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(config.auth.jwt_secret.as_ref()))
 }
 
-pub fn verify_jwt(token: &str) -> JwtResult<Claims> {
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "mysecret".to_string());
-    let token_data = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_ref()), &Validation::default())?;
+pub fn verify_jwt(config: &Config, token: &str) -> JwtResult<Claims> {
+    let token_data = decode::<Claims>(token, &DecodingKey::from_secret(config.auth.jwt_secret.as_ref()), &Validation::default())?;
     Ok(token_data.claims)
 }
 
-#[post("/lime/authenticate")]
-async fn authenticate(auth_data: web::Json<AuthData>) -> impl Responder {
-    // Currently only these users are supported (no registration possible rn)
-    let users = vec!["alice", "bob", "carol", "dave"];
+/// Users allowed to call the `/lime/admin` endpoints, from `auth.admin_users`
+pub fn is_admin(config: &Config, username: &str) -> bool {
+    config.auth.admin_users.iter().any(|admin| admin == username)
+}
 
-    if users.contains(&auth_data.username.as_str()) && auth_data.username == auth_data.password {
-        match create_jwt(&auth_data.username) {
+/// The admin the request is authenticated as, otherwise the response to answer
+/// with: `401` without a valid token and `403` for any other user
+pub async fn require_admin(req: &HttpRequest) -> Result<String, HttpResponse> {
+    let user = match get_user_from_token(req).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(HttpResponse::Unauthorized().body("Invalid or missing AUTH_TOKEN")),
+        Err(e) => return Err(HttpResponse::from_error(e)),
+    };
+    match req.app_data::<web::Data<Config>>() {
+        Some(config) if is_admin(config, &user) => Ok(user),
+        _ => Err(HttpResponse::Forbidden().body("Admin access required")),
+    }
+}
+
+#[post("/lime/authenticate")]
+async fn authenticate(auth_data: web::Json<AuthData>, pool: web::Data<DBPool>, config: web::Data<Config>) -> impl Responder {
+    match verify_credentials(&pool, &auth_data.username, &auth_data.password).await {
+        Ok(true) => match create_jwt(&config, &auth_data.username) {
             Ok(token) => {
                 let response = TokenResponse { token };
                 HttpResponse::Ok().json(response)
             }
             Err(_) => HttpResponse::InternalServerError().body("Failed to create token"),
+        },
+        Ok(false) => HttpResponse::Unauthorized().body("Invalid username or password"),
+        Err(e) => {
+            error!(error = %e, "Failed to check credentials");
+            HttpResponse::InternalServerError().body("Failed to check credentials")
         }
-    } else {
-        HttpResponse::Unauthorized().body("Invalid username or password")
     }
 }
 
@@ -75,8 +92,13 @@ mod tests {
 
     #[test]
     fn test_verify_jwt() {
-        let token = create_jwt("alice").unwrap();
-        let claims = verify_jwt(&token).unwrap();
+        let mut config = Config::default();
+        config.auth.jwt_secret = "secret".to_string();
+        let token = create_jwt(&config, "alice").unwrap();
+        let claims = verify_jwt(&config, &token).unwrap();
         assert_eq!(claims.username, "alice");
+
+        config.auth.jwt_secret = "other secret".to_string();
+        assert!(verify_jwt(&config, &token).is_err());
     }
 }
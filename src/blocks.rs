@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_web::{web, get, Responder, HttpResponse, HttpRequest};
+use serde::Serialize;
+use diesel::prelude::*;
+
+use ethers::prelude::*;
+use ethers::types::Block as EthersBlock;
+use tracing::error;
+
+use crate::DBPool;
+use crate::db::with_conn;
+use crate::prices::CsvPriceSource;
+use crate::response::{ResponseOptions, transaction_views};
+use crate::schema::blocks;
+use crate::config::Config;
+use crate::transaction::{EthProvider, get_block_transactions_from_db};
+
+/// Header fields of a block holding cached transactions
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[diesel(table_name = blocks)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub hash: String,
+    pub number: i64,
+    pub parent_hash: String,
+    /// Unix time in seconds
+    pub timestamp: i64,
+    pub miner: String,
+    pub gas_used: i64,
+    pub gas_limit: i64,
+    pub base_fee_per_gas: Option<String>,
+    pub transaction_count: i64,
+}
+
+impl Block {
+    /// `None` for pending blocks, which have neither a hash nor a number yet
+    fn from_ethers(block: &EthersBlock<H256>) -> Option<Self> {
+        Some(Block {
+            hash: format!("{:?}", block.hash?),
+            number: block.number?.as_u64() as i64,
+            parent_hash: format!("{:?}", block.parent_hash),
+            timestamp: block.timestamp.low_u64() as i64,
+            miner: format!("{:?}", block.author.unwrap_or_default()),
+            gas_used: block.gas_used.low_u64() as i64,
+            gas_limit: block.gas_limit.low_u64() as i64,
+            base_fee_per_gas: block.base_fee_per_gas.map(|base_fee| base_fee.to_string()),
+            transaction_count: block.transactions.len() as i64,
+        })
+    }
+}
+
+/// `None` when the node doesn't know the block (or it is still pending)
+pub async fn fetch_block(provider: &EthProvider, block_id: BlockId) -> Result<Option<Block>, ProviderError> {
+    Ok(provider.get_block(block_id).await?.as_ref().and_then(Block::from_ethers))
+}
+
+pub async fn store_block_in_db(pool: &DBPool, block: &Block) -> Result<(), diesel::result::Error> {
+    use crate::schema::blocks::dsl::*;
+
+    let block = block.clone();
+    with_conn(pool, move |conn| {
+        diesel::insert_into(blocks)
+            .values(&block)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }).await
+}
+
+pub async fn get_block_by_hash(pool: &DBPool, block_hash: &str) -> Result<Option<Block>, diesel::result::Error> {
+    use crate::schema::blocks::dsl::*;
+
+    let block_hash = block_hash.to_string();
+    with_conn(pool, move |conn| blocks.filter(hash.eq(block_hash)).first::<Block>(conn).optional()).await
+}
+
+/// Stored blocks at the height, more than one once a reorged block and its
+/// replacement were both stored. Ordered by hash.
+pub async fn get_blocks_by_number(pool: &DBPool, block_number: i64) -> Result<Vec<Block>, diesel::result::Error> {
+    use crate::schema::blocks::dsl::*;
+
+    with_conn(pool, move |conn| blocks.filter(number.eq(block_number)).order(hash).load::<Block>(conn)).await
+}
+
+/// Timestamps of the given blocks, keyed by block hash. Blocks not stored are left out.
+pub async fn get_block_timestamps(pool: &DBPool, block_hashes: &[String]) -> Result<HashMap<String, i64>, diesel::result::Error> {
+    use crate::schema::blocks::dsl::*;
+
+    let block_hashes = block_hashes.to_vec();
+    with_conn(pool, move |conn| {
+        Ok(blocks
+            .filter(hash.eq_any(block_hashes))
+            .select((hash, timestamp))
+            .load::<(String, i64)>(conn)?
+            .into_iter()
+            .collect())
+    }).await
+}
+
+/// Stores the block of a fetched transaction unless it is already known
+pub async fn capture_block(pool: &DBPool, provider: &EthProvider, block_hash: &str) {
+    match get_block_by_hash(pool, block_hash).await {
+        Ok(Some(_)) => return,
+        Ok(None) => {}
+        Err(e) => {
+            error!(error = %e, "Failed to fetch block");
+            return;
+        }
+    }
+
+    let Ok(hash) = H256::from_str(block_hash) else {
+        return;
+    };
+    match fetch_block(provider, BlockId::Hash(hash)).await {
+        Ok(Some(block)) => {
+            if let Err(e) = store_block_in_db(pool, &block).await {
+                error!(error = %e, "Failed to save block");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => error!(error = %e, block_hash, "Failed to fetch block from the node"),
+    }
+}
+
+/// `{number|hash}` path segment of `/lime/block`
+#[derive(Debug, PartialEq)]
+pub enum BlockRef {
+    Number(u64),
+    Hash(H256),
+}
+
+impl FromStr for BlockRef {
+    type Err = ();
+
+    /// A 32 byte hex string is a hash, anything else a decimal or `0x` prefixed number
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.len() == 66 && (value.starts_with("0x") || value.starts_with("0X")) {
+            return H256::from_str(value).map(BlockRef::Hash).map_err(|_| ());
+        }
+        match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).map(BlockRef::Number).map_err(|_| ()),
+            None => value.parse::<u64>().map(BlockRef::Number).map_err(|_| ()),
+        }
+    }
+}
+
+#[get("/lime/block/{block}")]
+pub async fn lime_block(path: web::Path<String>, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, csv_prices: web::Data<CsvPriceSource>, req: HttpRequest) -> impl Responder {
+    let options = match ResponseOptions::from_request(&req) {
+        Ok(options) => options,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let Ok(block_ref) = BlockRef::from_str(&path.into_inner()) else {
+        return HttpResponse::BadRequest().body("Invalid Block Number or Hash");
+    };
+
+    let stored = match &block_ref {
+        BlockRef::Hash(hash) => get_block_by_hash(&pool, &format!("{:?}", hash)).await,
+        // Several blocks at the height are left to the node to tell the canonical one
+        BlockRef::Number(number) => get_blocks_by_number(&pool, *number as i64).await
+            .map(|mut blocks| if blocks.len() == 1 { blocks.pop() } else { None }),
+    };
+    let block = match stored {
+        Ok(Some(block)) => block,
+        Ok(None) => {
+            let block_id = match block_ref {
+                BlockRef::Hash(hash) => BlockId::Hash(hash),
+                BlockRef::Number(number) => BlockId::Number(BlockNumber::Number(number.into())),
+            };
+            let block = match fetch_block(&provider, block_id).await {
+                Ok(Some(block)) => block,
+                Ok(None) => return HttpResponse::NotFound().body("Block not found"),
+                Err(e) => {
+                    error!(error = %e, "Failed to fetch block from the node");
+                    return HttpResponse::BadGateway().body("Failed to fetch block");
+                }
+            };
+            if let Err(e) = store_block_in_db(&pool, &block).await {
+                error!(error = %e, "Failed to save block");
+            }
+            block
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to fetch block");
+            return HttpResponse::InternalServerError().body("Failed to fetch block");
+        }
+    };
+
+    let transactions = match get_block_transactions_from_db(&pool, &block.hash).await {
+        Ok(transactions) => transactions,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch block transactions");
+            return HttpResponse::InternalServerError().body("Failed to fetch block transactions");
+        }
+    };
+
+    let response = serde_json::json!({
+        "block": block,
+        "transactions": options.format_transactions(&transaction_views(&pool, &config, &csv_prices, transactions).await),
+    });
+    HttpResponse::Ok().json(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_ref_from_str() {
+        let hash = "0xabababababababababababababababababababababababababababababababab";
+        assert_eq!(BlockRef::from_str(hash), Ok(BlockRef::Hash(H256::repeat_byte(0xab))));
+        assert_eq!(BlockRef::from_str("5703857"), Ok(BlockRef::Number(5703857)));
+        assert_eq!(BlockRef::from_str("0x5708b1"), Ok(BlockRef::Number(5703857)));
+        assert!(BlockRef::from_str("latest").is_err());
+        assert!(BlockRef::from_str("0x").is_err());
+        assert!(BlockRef::from_str(&hash[..65]).is_err());
+    }
+
+    #[test]
+    fn test_block_from_ethers() {
+        let block = EthersBlock::<H256> {
+            hash: Some(H256::repeat_byte(0xab)),
+            number: Some(U64::from(5703857)),
+            parent_hash: H256::repeat_byte(0xcd),
+            timestamp: U256::from(1719726496),
+            author: Some(Address::repeat_byte(0x44)),
+            gas_used: U256::from(21000),
+            gas_limit: U256::from(30_000_000),
+            base_fee_per_gas: Some(U256::from(7)),
+            transactions: vec![H256::repeat_byte(0x01), H256::repeat_byte(0x02)],
+            ..Default::default()
+        };
+
+        let stored = Block::from_ethers(&block).unwrap();
+        assert_eq!(stored.number, 5703857);
+        assert_eq!(stored.timestamp, 1719726496);
+        assert_eq!(stored.miner, "0x4444444444444444444444444444444444444444");
+        assert_eq!(stored.base_fee_per_gas.as_deref(), Some("7"));
+        assert_eq!(stored.transaction_count, 2);
+
+        let pending = EthersBlock::<H256> { hash: None, ..block };
+        assert!(Block::from_ethers(&pending).is_none());
+    }
+}
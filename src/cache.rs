@@ -0,0 +1,460 @@
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::{web, delete, get, HttpRequest, HttpResponse, Responder};
+use actix_web_lab::extract::Query;
+use chrono::Utc;
+use diesel::pg::upsert::excluded;
+use diesel::prelude::*;
+use ethers::types::H256;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::DBPool;
+use crate::auth::require_admin;
+use crate::config::Config;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::metrics::record_memory_cache_lookup;
+use crate::transaction::Transaction;
+use crate::schema::{
+    blocks,
+    token_metadata,
+    token_transfers,
+    transaction_fees,
+    transaction_logs,
+    transaction_traces,
+    transactions,
+    unknown_transactions,
+    users_searches,
+};
+
+/// Rows of everything cached from the ETH node
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub transactions: i64,
+    pub transaction_fees: i64,
+    pub transaction_logs: i64,
+    pub token_transfers: i64,
+    pub transaction_traces: i64,
+    pub blocks: i64,
+    pub token_metadata: i64,
+    pub user_searches: i64,
+    pub unknown_transactions: i64,
+}
+
+pub async fn cache_stats(pool: &DBPool) -> Result<CacheStats, diesel::result::Error> {
+    with_conn(pool, |conn| {
+        Ok(CacheStats {
+            transactions: transactions::table.count().get_result(conn)?,
+            transaction_fees: transaction_fees::table.count().get_result(conn)?,
+            transaction_logs: transaction_logs::table.count().get_result(conn)?,
+            token_transfers: token_transfers::table.count().get_result(conn)?,
+            transaction_traces: transaction_traces::table.count().get_result(conn)?,
+            blocks: blocks::table.count().get_result(conn)?,
+            token_metadata: token_metadata::table.count().get_result(conn)?,
+            user_searches: users_searches::table.count().get_result(conn)?,
+            unknown_transactions: unknown_transactions::table.count().get_result(conn)?,
+        })
+    }).await
+}
+
+/// Deletes the given transactions, or all of them along with the cached blocks
+/// and token metadata when `tx_hashes` is `None`, so they are fetched again.
+/// The purged hashes remembered as unknown go too. Users' searches are kept,
+/// they are history rather than cache. Returns the number of transactions
+/// deleted.
+pub async fn purge_transactions(pool: &DBPool, tx_hashes: Option<&[String]>) -> Result<usize, diesel::result::Error> {
+    let tx_hashes = tx_hashes.map(<[String]>::to_vec);
+    with_conn(pool, move |conn| conn.transaction(|conn| match tx_hashes {
+        Some(tx_hashes) => {
+            diesel::delete(token_transfers::table.filter(token_transfers::transaction_hash.eq_any(&tx_hashes))).execute(conn)?;
+            diesel::delete(transaction_logs::table.filter(transaction_logs::transaction_hash.eq_any(&tx_hashes))).execute(conn)?;
+            diesel::delete(transaction_fees::table.filter(transaction_fees::transaction_hash.eq_any(&tx_hashes))).execute(conn)?;
+            diesel::delete(transaction_traces::table.filter(transaction_traces::transaction_hash.eq_any(&tx_hashes))).execute(conn)?;
+            diesel::delete(unknown_transactions::table.filter(unknown_transactions::transaction_hash.eq_any(&tx_hashes))).execute(conn)?;
+            diesel::delete(transactions::table.filter(transactions::transaction_hash.eq_any(tx_hashes))).execute(conn)
+        }
+        None => {
+            diesel::delete(token_transfers::table).execute(conn)?;
+            diesel::delete(transaction_logs::table).execute(conn)?;
+            diesel::delete(transaction_fees::table).execute(conn)?;
+            diesel::delete(transaction_traces::table).execute(conn)?;
+            diesel::delete(blocks::table).execute(conn)?;
+            diesel::delete(token_metadata::table).execute(conn)?;
+            diesel::delete(unknown_transactions::table).execute(conn)?;
+            diesel::delete(transactions::table).execute(conn)
+        }
+    })).await
+}
+
+/// Hit rate of the in-memory cache since the server started
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// `hits / (hits + misses)`, 0 before the first lookup
+    pub hit_rate: f64,
+    /// Hashes remembered as unknown to the node
+    pub unknown_entries: usize,
+    /// Lookups of those that weren't sent to the node
+    pub unknown_hits: u64,
+}
+
+/// Entries are only ever replaced whole, a panic while holding the lock can't leave one half written
+fn lock<T>(mutex: &Option<Mutex<T>>) -> Option<MutexGuard<'_, T>> {
+    mutex.as_ref().map(|mutex| mutex.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// How long the chain head seen last is used before the node is asked again
+const HEAD_TTL: Duration = Duration::from_secs(12);
+
+/// Bounded LRU of transactions in front of the `transactions` table, so hot
+/// hashes don't cost a database round-trip. Entries expire after `ttl` and are
+/// dropped along with the database rows when purged. Hashes the node didn't
+/// know are remembered for `unknown_ttl` in a second LRU of the same size.
+///
+/// Only transactions `min_confirmations` deep below the chain head are kept,
+/// so a reorg never leaves one of its dropped transactions in memory.
+pub struct TransactionCache {
+    /// `None` when disabled (`cache.memory_size = 0`)
+    entries: Option<Mutex<LruCache<String, (Transaction, Instant)>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// `None` when disabled (`cache.memory_size = 0` or `cache.negative_ttl_secs = 0`)
+    unknown: Option<Mutex<LruCache<String, Instant>>>,
+    unknown_ttl: Duration,
+    unknown_hits: AtomicU64,
+    min_confirmations: u64,
+    /// Latest block number seen on the node, and when it was asked for
+    head: Mutex<Option<(u64, Instant)>>,
+}
+
+impl TransactionCache {
+    pub fn new(size: usize, ttl: Duration, unknown_ttl: Duration, min_confirmations: u64) -> Self {
+        let unknown_size = if unknown_ttl.is_zero() { None } else { NonZeroUsize::new(size) };
+        TransactionCache {
+            entries: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            unknown: unknown_size.map(|size| Mutex::new(LruCache::new(size))),
+            unknown_ttl,
+            unknown_hits: AtomicU64::new(0),
+            min_confirmations,
+            head: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.cache.memory_size, config.memory_cache_ttl(), config.negative_cache_ttl(), config.cache.min_confirmations)
+    }
+
+    /// Cache that never holds anything, for one-off commands
+    pub fn disabled() -> Self {
+        Self::new(0, Duration::ZERO, Duration::ZERO, 0)
+    }
+
+    /// Whether the chain head should be asked for (and passed to `observe_head`)
+    /// before inserting, as the one seen last is missing or too old
+    pub fn needs_head(&self) -> bool {
+        let head = self.head.lock().unwrap_or_else(PoisonError::into_inner);
+        self.entries.is_some() && self.min_confirmations > 0 && head.is_none_or(|(_, seen)| seen.elapsed() >= HEAD_TTL)
+    }
+
+    pub fn observe_head(&self, block_number: u64) {
+        let mut head = self.head.lock().unwrap_or_else(PoisonError::into_inner);
+        let latest = head.map_or(block_number, |(seen, _)| seen.max(block_number));
+        *head = Some((latest, Instant::now()));
+    }
+
+    /// Whether the transaction is deep enough not to be reorged out anymore
+    fn is_final(&self, transaction: &Transaction) -> bool {
+        if self.min_confirmations == 0 {
+            return true;
+        }
+        let head = self.head.lock().unwrap_or_else(PoisonError::into_inner);
+        head.is_some_and(|(head, _)| (transaction.block_number.max(0) as u64).saturating_add(self.min_confirmations) <= head + 1)
+    }
+
+    /// The transaction, unless it isn't cached or has expired
+    pub fn get(&self, tx_hash: &str) -> Option<Transaction> {
+        let mut entries = lock(&self.entries)?;
+        let transaction = match entries.get(tx_hash) {
+            Some((transaction, inserted)) if inserted.elapsed() < self.ttl => Some(transaction.clone()),
+            Some(_) => {
+                entries.pop(tx_hash);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if transaction.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        record_memory_cache_lookup(transaction.is_some());
+        transaction
+    }
+
+    /// Caches the transaction once final, the node evidently knows it now
+    pub fn insert(&self, transaction: &Transaction) {
+        if let Some(mut entries) = lock(&self.entries).filter(|_| self.is_final(transaction)) {
+            entries.put(transaction.transaction_hash.clone(), (transaction.clone(), Instant::now()));
+        }
+        if let Some(mut unknown) = lock(&self.unknown) {
+            unknown.pop(&transaction.transaction_hash);
+        }
+    }
+
+    /// Whether the node didn't know the hash within the last `unknown_ttl`
+    pub fn is_unknown(&self, tx_hash: &str) -> bool {
+        let Some(mut unknown) = lock(&self.unknown) else { return false };
+        let is_unknown = match unknown.get(tx_hash) {
+            Some(checked) if checked.elapsed() < self.unknown_ttl => true,
+            Some(_) => {
+                unknown.pop(tx_hash);
+                false
+            }
+            None => false,
+        };
+        if is_unknown {
+            self.unknown_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        is_unknown
+    }
+
+    pub fn insert_unknown(&self, tx_hash: &str) {
+        if let Some(mut unknown) = lock(&self.unknown) {
+            unknown.put(tx_hash.to_string(), Instant::now());
+        }
+    }
+
+    /// Drops the given transactions (and unknown hashes), or everything when `tx_hashes` is `None`
+    pub fn invalidate(&self, tx_hashes: Option<&[String]>) {
+        if let Some(mut entries) = lock(&self.entries) {
+            match tx_hashes {
+                Some(tx_hashes) => tx_hashes.iter().for_each(|tx_hash| { entries.pop(tx_hash); }),
+                None => entries.clear(),
+            }
+        }
+        if let Some(mut unknown) = lock(&self.unknown) {
+            match tx_hashes {
+                Some(tx_hashes) => tx_hashes.iter().for_each(|tx_hash| { unknown.pop(tx_hash); }),
+                None => unknown.clear(),
+            }
+        }
+    }
+
+    pub fn stats(&self) -> MemoryCacheStats {
+        let (entries, capacity) = lock(&self.entries)
+            .map(|entries| (entries.len(), entries.cap().get()))
+            .unwrap_or_default();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let hit_rate = match hits + misses {
+            0 => 0.0,
+            lookups => hits as f64 / lookups as f64,
+        };
+        MemoryCacheStats {
+            entries,
+            capacity,
+            hits,
+            misses,
+            hit_rate,
+            unknown_entries: lock(&self.unknown).map_or(0, |unknown| unknown.len()),
+            unknown_hits: self.unknown_hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The given hashes the node didn't know within the last `ttl`, from the `unknown_transactions` table
+pub async fn get_unknown_transactions(pool: &DBPool, tx_hashes: &[String], ttl: Duration) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::unknown_transactions::dsl::*;
+
+    let tx_hashes = tx_hashes.to_vec();
+    let since = Utc::now().timestamp() - ttl.as_secs() as i64;
+    with_conn(pool, move |conn| {
+        unknown_transactions
+            .select(transaction_hash)
+            .filter(transaction_hash.eq_any(tx_hashes))
+            .filter(checked_at.gt(since))
+            .load(conn)
+    }).await
+}
+
+/// Remembers the hashes as unknown as of now, dropping the ones that expired
+/// after `ttl` on the way so the table doesn't grow forever
+pub fn insert_unknown_transactions(conn: &mut PgConnection, tx_hashes: &[String], ttl: Duration) -> Result<usize, diesel::result::Error> {
+    use crate::schema::unknown_transactions::dsl::*;
+
+    let now = Utc::now().timestamp();
+    diesel::delete(unknown_transactions.filter(checked_at.le(now - ttl.as_secs() as i64))).execute(conn)?;
+
+    let rows: Vec<_> = tx_hashes.iter().map(|hash| (transaction_hash.eq(hash), checked_at.eq(now))).collect();
+    let mut inserted = 0;
+    for chunk in rows.chunks(MAX_BIND_PARAMS / 2) {
+        inserted += diesel::insert_into(unknown_transactions)
+            .values(chunk)
+            .on_conflict(transaction_hash)
+            .do_update()
+            .set(checked_at.eq(excluded(checked_at)))
+            .execute(conn)?;
+    }
+    Ok(inserted)
+}
+
+/// Row counts of the database cache and hit rate of the in-memory one, admin only
+#[get("/lime/admin/cache")]
+pub async fn lime_cache_stats(pool: web::Data<DBPool>, cache: web::Data<TransactionCache>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    match cache_stats(&pool).await {
+        Ok(stats) => HttpResponse::Ok().json(serde_json::json!({ "database": stats, "memory": cache.stats() })),
+        Err(e) => {
+            error!(error = %e, "Failed to count cached rows");
+            HttpResponse::InternalServerError().body("Failed to count cached rows")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeParams {
+    #[serde(rename = "transactionHashes", default)]
+    hashes: Vec<String>,
+    #[serde(default)]
+    all: bool,
+}
+
+/// Same as `cache purge`, but also drops the purged transactions from the
+/// in-memory cache of the running server, admin only
+#[delete("/lime/admin/cache")]
+pub async fn lime_cache_purge(query: Query<PurgeParams>, pool: web::Data<DBPool>, cache: web::Data<TransactionCache>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let PurgeParams { hashes, all } = query.into_inner();
+    let tx_hashes = match (hashes.is_empty(), all) {
+        (true, true) => None,
+        (false, false) => {
+            // Stored hashes are lowercase `0x` hex, as formatted by `H256`
+            let parsed: Option<Vec<String>> = hashes.iter().map(|hash| H256::from_str(hash).ok().map(|hash| format!("{:?}", hash))).collect();
+            match parsed {
+                Some(tx_hashes) => Some(tx_hashes),
+                None => return HttpResponse::BadRequest().body("Invalid Transaction Hash provided!"),
+            }
+        }
+        _ => return HttpResponse::BadRequest().body("Expected either transactionHashes or all=true"),
+    };
+
+    match purge_transactions(&pool, tx_hashes.as_deref()).await {
+        Ok(purged) => {
+            cache.invalidate(tx_hashes.as_deref());
+            HttpResponse::Ok().json(serde_json::json!({ "purged": purged }))
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to purge transactions");
+            HttpResponse::InternalServerError().body("Failed to purge transactions")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_transaction;
+
+    fn transaction(tx_hash: &str) -> Transaction {
+        Transaction {
+            from: "0xf1".to_string(),
+            ..test_transaction(tx_hash, "0xb1", 1)
+        }
+    }
+
+    #[test]
+    fn test_transaction_cache() {
+        let cache = TransactionCache::new(2, Duration::from_secs(60), Duration::from_secs(60), 0);
+        cache.insert(&transaction("0x01"));
+        cache.insert(&transaction("0x02"));
+        assert_eq!(cache.get("0x01"), Some(transaction("0x01")));
+
+        // 0x02 is the least recently used one
+        cache.insert(&transaction("0x03"));
+        assert_eq!(cache.get("0x02"), None);
+        assert!(cache.get("0x03").is_some());
+
+        cache.invalidate(Some(&["0x03".to_string()]));
+        assert_eq!(cache.get("0x03"), None);
+        cache.invalidate(None);
+        assert_eq!(cache.get("0x01"), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.capacity, stats.hits, stats.misses, stats.hit_rate), (0, 2, 2, 3, 0.4));
+    }
+
+    #[test]
+    fn test_transaction_cache_expiry() {
+        let cache = TransactionCache::new(2, Duration::ZERO, Duration::ZERO, 0);
+        cache.insert(&transaction("0x01"));
+        assert_eq!(cache.get("0x01"), None);
+        cache.insert_unknown("0x02");
+        assert!(!cache.is_unknown("0x02"));
+        assert_eq!(cache.stats().entries, 0);
+
+        let disabled = TransactionCache::disabled();
+        disabled.insert(&transaction("0x01"));
+        assert_eq!(disabled.get("0x01"), None);
+        assert_eq!(disabled.stats(), MemoryCacheStats { entries: 0, capacity: 0, hits: 0, misses: 0, hit_rate: 0.0, unknown_entries: 0, unknown_hits: 0 });
+    }
+
+    #[test]
+    fn test_unknown_hashes() {
+        let cache = TransactionCache::new(2, Duration::from_secs(60), Duration::from_secs(60), 0);
+        cache.insert_unknown("0x01");
+        cache.insert_unknown("0x02");
+        assert!(cache.is_unknown("0x01"));
+        assert!(!cache.is_unknown("0x03"));
+
+        // Found after all, e.g. once mined
+        cache.insert(&transaction("0x01"));
+        assert!(!cache.is_unknown("0x01"));
+
+        cache.invalidate(Some(&["0x02".to_string()]));
+        assert!(!cache.is_unknown("0x02"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.unknown_entries, stats.unknown_hits), (1, 0, 1));
+    }
+
+    #[test]
+    fn test_transaction_cache_confirmations() {
+        let cache = TransactionCache::new(2, Duration::from_secs(60), Duration::from_secs(60), 3);
+        let transaction = |tx_hash: &str, block_number: i64| Transaction { block_number, ..test_transaction(tx_hash, "0xb1", 0) };
+        assert!(cache.needs_head());
+
+        // Nothing is final before the chain head is known
+        cache.insert(&transaction("0x01", 1));
+        assert_eq!(cache.get("0x01"), None);
+
+        cache.observe_head(10);
+        assert!(!cache.needs_head());
+        cache.insert(&transaction("0x01", 8));
+        cache.insert(&transaction("0x02", 9));
+        assert!(cache.get("0x01").is_some());
+        assert_eq!(cache.get("0x02"), None);
+
+        // An older head seen later doesn't move it back
+        cache.observe_head(5);
+        cache.insert(&transaction("0x03", 8));
+        assert!(cache.get("0x03").is_some());
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::{Command as Process, Stdio};
+use std::str::FromStr;
+
+use actix_web::web;
+use ethers::types::H256;
+
+use crate::DBPool;
+use crate::analytics::run_export_command;
+use crate::cache::{cache_stats, purge_transactions, TransactionCache};
+use crate::config::Config;
+use crate::import::run_import_command;
+use crate::prices::CsvPriceSource;
+use crate::response::transaction_views;
+use crate::routes::lookup_transactions;
+use crate::setup::{migration_status, revert_last_migration, run_migrations};
+use crate::users::{add_user, list_users, remove_user};
+
+pub const USAGE: &str = "\
+Usage: ethereum-fetcher [command]
+
+Commands:
+  serve                               Run the server (the default)
+  migrate up|down|status              Apply the pending migrations, revert the last one or list them
+  fetch [--refresh] <hash>...         Look up transactions and print them as JSON, --refresh retries unknown hashes
+  users add <username>                Register a user, prompting for the password (or reading it from stdin)
+  users remove <username>             Remove a user and their search history
+  users list                          List the registered users
+  cache purge --all|<hash>...         Delete cached transactions so they are fetched again
+  cache stats                         Count the cached rows per table
+  export-parquet --out <dir> [...]    Export transactions to Parquet files
+  import <file> [...]                 Import transactions from a JSON, NDJSON or CSV dump
+  help                                Show this message";
+
+#[derive(Debug, PartialEq)]
+pub enum MigrateCommand {
+    Up,
+    Down,
+    Status,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UsersCommand {
+    Add { username: String },
+    Remove { username: String },
+    List,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CacheCommand {
+    /// `None` purges everything
+    Purge { tx_hashes: Option<Vec<String>> },
+    Stats,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    Migrate(MigrateCommand),
+    /// `refresh` asks the node again for hashes it recently didn't know
+    Fetch { tx_hashes: Vec<H256>, refresh: bool },
+    Users(UsersCommand),
+    Cache(CacheCommand),
+    /// Options are parsed by `run_export_command`
+    ExportParquet(Vec<String>),
+    /// Options are parsed by `run_import_command`
+    Import(Vec<String>),
+    Help,
+}
+
+fn parse_hashes(args: &[String]) -> Result<Vec<H256>, String> {
+    args.iter()
+        .map(|arg| H256::from_str(arg).map_err(|_| format!("Invalid transaction hash: {}", arg)))
+        .collect()
+}
+
+impl Command {
+    /// Parses the arguments following the binary name
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] | ["serve"] => Ok(Command::Serve),
+            ["help" | "--help" | "-h"] => Ok(Command::Help),
+            ["migrate", "up"] => Ok(Command::Migrate(MigrateCommand::Up)),
+            ["migrate", "down"] => Ok(Command::Migrate(MigrateCommand::Down)),
+            ["migrate", "status"] => Ok(Command::Migrate(MigrateCommand::Status)),
+            ["migrate", ..] => Err("Usage: migrate up|down|status".to_string()),
+            ["fetch"] | ["fetch", "--refresh"] => Err("Usage: fetch [--refresh] <hash>...".to_string()),
+            ["fetch", "--refresh", hashes @ ..] => Ok(Command::Fetch { tx_hashes: parse_hashes(&args_from(hashes))?, refresh: true }),
+            ["fetch", hashes @ ..] => Ok(Command::Fetch { tx_hashes: parse_hashes(&args_from(hashes))?, refresh: false }),
+            ["users", "add", username] => Ok(Command::Users(UsersCommand::Add { username: username.to_string() })),
+            ["users", "remove", username] => Ok(Command::Users(UsersCommand::Remove { username: username.to_string() })),
+            ["users", "list"] => Ok(Command::Users(UsersCommand::List)),
+            ["users", ..] => Err("Usage: users add <username> | users remove <username> | users list".to_string()),
+            ["cache", "purge", "--all"] => Ok(Command::Cache(CacheCommand::Purge { tx_hashes: None })),
+            ["cache", "purge", hashes @ ..] if !hashes.is_empty() => {
+                // Stored hashes are lowercase `0x` hex, as formatted by `H256`
+                let tx_hashes = parse_hashes(&args_from(hashes))?.iter().map(|hash| format!("{:?}", hash)).collect();
+                Ok(Command::Cache(CacheCommand::Purge { tx_hashes: Some(tx_hashes) }))
+            }
+            ["cache", "stats"] => Ok(Command::Cache(CacheCommand::Stats)),
+            ["cache", ..] => Err("Usage: cache purge --all|<hash>... | cache stats".to_string()),
+            ["export-parquet", rest @ ..] => Ok(Command::ExportParquet(args_from(rest))),
+            ["import", rest @ ..] => Ok(Command::Import(args_from(rest))),
+            [command, ..] => Err(format!("Unknown command: {}", command)),
+        }
+    }
+
+    /// Everything but `migrate` (and `help`) needs an up to date schema
+    pub fn needs_migrations(&self) -> bool {
+        !matches!(self, Command::Migrate(_) | Command::Help)
+    }
+}
+
+fn args_from(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Turns the terminal echo on or off, best effort as `stty` may be missing
+fn set_echo(on: bool) {
+    let _ = Process::new("stty").arg(if on { "echo" } else { "-echo" }).stdin(Stdio::inherit()).status();
+}
+
+/// Reads the password from stdin, prompting for it without echo on a terminal.
+/// Never taken as an argument, which would leave it in the shell history and `ps`.
+fn read_password() -> Result<String, String> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut password = String::new();
+    let read = match interactive {
+        true => {
+            eprint!("Password: ");
+            let _ = io::stderr().flush();
+            set_echo(false);
+            let read = stdin.lock().read_line(&mut password);
+            set_echo(true);
+            eprintln!();
+            read
+        }
+        false => stdin.lock().read_line(&mut password),
+    };
+    read.map_err(|e| format!("Couldn't read the password: {}", e))?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    match password.is_empty() {
+        true => Err("The password must not be empty".to_string()),
+        false => Ok(password),
+    }
+}
+
+async fn run_migrate(pool: &DBPool, command: MigrateCommand) -> Result<(), String> {
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    match command {
+        MigrateCommand::Up => {
+            let applied = run_migrations(&mut conn)?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for version in applied {
+                println!("Applied {}", version);
+            }
+        }
+        MigrateCommand::Down => println!("Reverted {}", revert_last_migration(&mut conn)?),
+        MigrateCommand::Status => {
+            for (name, applied) in migration_status(&mut conn)? {
+                println!("[{}] {}", if applied { "x" } else { " " }, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_fetch(pool: &DBPool, config: &Config, tx_hashes: &[H256], refresh: bool) -> Result<(), String> {
+    let pool = web::Data::new(pool.clone());
+    let provider = config.eth_provider()?;
+    let csv_prices = CsvPriceSource::from_config(config)?;
+    let transactions = lookup_transactions(&pool, config, &provider, &TransactionCache::disabled(), tx_hashes, refresh, None).await;
+    for hash in tx_hashes {
+        let hash = format!("{:?}", hash);
+        if !transactions.iter().any(|transaction| transaction.transaction_hash == hash) {
+            eprintln!("Not found: {}", hash);
+        }
+    }
+    let transactions = transaction_views(&pool, config, &csv_prices, transactions).await;
+    let json = serde_json::json!({ "transactions": transactions });
+    println!("{}", serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+async fn run_users(pool: &DBPool, command: UsersCommand) -> Result<(), String> {
+    match command {
+        UsersCommand::Add { username } => {
+            let password = read_password()?;
+            match add_user(pool, &username, &password).await? {
+                true => println!("Added user {}", username),
+                false => return Err(format!("User {} already exists", username)),
+            }
+        }
+        UsersCommand::Remove { username } => match remove_user(pool, &username).await.map_err(|e| e.to_string())? {
+            true => println!("Removed user {}", username),
+            false => return Err(format!("No such user: {}", username)),
+        },
+        UsersCommand::List => {
+            for username in list_users(pool).await.map_err(|e| e.to_string())? {
+                println!("{}", username);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_cache(pool: &DBPool, command: CacheCommand) -> Result<(), String> {
+    match command {
+        CacheCommand::Purge { tx_hashes } => {
+            let purged = purge_transactions(pool, tx_hashes.as_deref()).await.map_err(|e| e.to_string())?;
+            println!("Purged {} transactions", purged);
+        }
+        CacheCommand::Stats => {
+            let stats = cache_stats(pool).await.map_err(|e| e.to_string())?;
+            println!("{}", serde_json::to_string_pretty(&stats).map_err(|e| e.to_string())?);
+        }
+    }
+    Ok(())
+}
+
+/// Runs any command but `serve`
+pub async fn run(command: Command, pool: &DBPool, config: &Config) -> Result<(), String> {
+    match command {
+        Command::Serve => unreachable!("`serve` is run by `main`"),
+        Command::Help => println!("{}", USAGE),
+        Command::Migrate(command) => run_migrate(pool, command).await?,
+        Command::Fetch { tx_hashes, refresh } => run_fetch(pool, config, &tx_hashes, refresh).await?,
+        Command::Users(command) => run_users(pool, command).await?,
+        Command::Cache(command) => run_cache(pool, command).await?,
+        Command::ExportParquet(args) => run_export_command(pool, &args).await?,
+        Command::Import(args) => run_import_command(pool, &args)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(&args_from(args))
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse(&[]), Ok(Command::Serve));
+        assert_eq!(parse(&["serve"]), Ok(Command::Serve));
+        assert_eq!(parse(&["migrate", "status"]), Ok(Command::Migrate(MigrateCommand::Status)));
+        assert_eq!(parse(&["users", "add", "erin"]), Ok(Command::Users(UsersCommand::Add { username: "erin".to_string() })));
+        assert!(parse(&["users", "add", "erin", "secret"]).is_err());
+        assert_eq!(parse(&["cache", "purge", "--all"]), Ok(Command::Cache(CacheCommand::Purge { tx_hashes: None })));
+        assert_eq!(parse(&["import", "dump.csv", "--format", "csv"]), Ok(Command::Import(args_from(&["dump.csv", "--format", "csv"]))));
+
+        let hash = "0x22F4A0E8243F6BECD1E4E31FCE147244A5DE7CE604080854C516B324A186A59E";
+        let purge = CacheCommand::Purge { tx_hashes: Some(vec![hash.to_lowercase()]) };
+        assert_eq!(parse(&["cache", "purge", hash]), Ok(Command::Cache(purge)));
+        assert_eq!(parse(&["fetch", hash]), Ok(Command::Fetch { tx_hashes: vec![H256::from_str(hash).unwrap()], refresh: false }));
+        assert_eq!(parse(&["fetch", "--refresh", hash]), Ok(Command::Fetch { tx_hashes: vec![H256::from_str(hash).unwrap()], refresh: true }));
+
+        assert!(parse(&["fetch"]).is_err());
+        assert!(parse(&["fetch", "--refresh"]).is_err());
+        assert!(parse(&["fetch", "0x1234"]).is_err());
+        assert!(parse(&["cache", "purge"]).is_err());
+        assert!(parse(&["migrate", "sideways"]).is_err());
+        assert_eq!(parse(&["deploy"]), Err("Unknown command: deploy".to_string()));
+    }
+}
@@ -0,0 +1,35 @@
+use actix_web::web;
+use diesel::PgConnection;
+use diesel::result::{DatabaseErrorKind, Error};
+
+use crate::DBPool;
+
+/// Postgres limit of bind parameters in a single statement, multi-row inserts
+/// are split into chunks of `MAX_BIND_PARAMS / columns` rows
+pub const MAX_BIND_PARAMS: usize = 65_535;
+
+/// Error for a connection that couldn't be checked out of the pool (or a query
+/// that panicked), so callers handle it like any other failed query
+pub fn unavailable(message: String) -> Error {
+    Error::DatabaseError(DatabaseErrorKind::UnableToSendCommand, Box::new(message))
+}
+
+/// Runs `query` with a pooled connection on the blocking thread pool, so the
+/// synchronous Diesel calls don't stall the worker and its other requests.
+/// Waiting for a connection gives up after `database.connection_timeout_secs`.
+pub async fn with_conn<T, F>(pool: &DBPool, query: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PgConnection) -> Result<T, Error> + Send + 'static,
+{
+    let pool = pool.clone();
+    // Keeps the `db_query` spans nested in the request span on the blocking thread
+    let span = tracing::Span::current();
+    web::block(move || {
+        let _guard = span.enter();
+        let mut conn = pool.get().map_err(|e| unavailable(e.to_string()))?;
+        query(&mut conn)
+    })
+    .await
+    .map_err(|e| unavailable(e.to_string()))?
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use actix_web::{web, get, Responder, HttpResponse};
+use serde::{Deserialize, Serialize};
+use diesel::prelude::*;
+
+use ethers::abi::{Abi, Event, RawLog, HumanReadableParser};
+use ethers::types::{Address, Log, H256};
+use ethers::utils::hex;
+use tracing::error;
+
+use crate::DBPool;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::abi::{DecodeSource, DecodedArgument, get_contract_abis, token_to_json};
+use crate::schema::transaction_logs;
+
+/// Token standard events decoded for every contract, even without a registered ABI.
+/// ERC-20 and ERC-721 `Transfer` / `Approval` share their signature and are told
+/// apart by the number of indexed topics.
+const STANDARD_EVENTS: &[(&str, &str)] = &[
+    ("ERC-20", "event Transfer(address indexed from, address indexed to, uint256 value)"),
+    ("ERC-20", "event Approval(address indexed owner, address indexed spender, uint256 value)"),
+    ("ERC-721", "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)"),
+    ("ERC-721", "event Approval(address indexed owner, address indexed approved, uint256 indexed tokenId)"),
+    ("ERC-721", "event ApprovalForAll(address indexed owner, address indexed operator, bool approved)"),
+    ("ERC-1155", "event TransferSingle(address indexed operator, address indexed from, address indexed to, uint256 id, uint256 value)"),
+    ("ERC-1155", "event TransferBatch(address indexed operator, address indexed from, address indexed to, uint256[] ids, uint256[] values)"),
+    ("ERC-1155", "event URI(string value, uint256 indexed id)"),
+];
+
+/// Receipt log of a transaction, as stored in the DB
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[diesel(table_name = transaction_logs)]
+pub struct TransactionLog {
+    pub transaction_hash: String,
+    pub log_index: i64,
+    pub block_number: i64,
+    pub address: String,
+    pub topic0: Option<String>,
+    pub topic1: Option<String>,
+    pub topic2: Option<String>,
+    pub topic3: Option<String>,
+    pub data: String,
+}
+
+impl From<&Log> for TransactionLog {
+    fn from(log: &Log) -> Self {
+        let topic = |index: usize| log.topics.get(index).map(|topic| format!("{:?}", topic));
+        Self {
+            transaction_hash: format!("{:?}", log.transaction_hash.unwrap_or_default()),
+            log_index: log.log_index.unwrap_or_default().as_u64() as i64,
+            block_number: log.block_number.unwrap_or_default().as_u64() as i64,
+            address: format!("{:?}", log.address),
+            topic0: topic(0),
+            topic1: topic(1),
+            topic2: topic(2),
+            topic3: topic(3),
+            data: log.data.to_string(),
+        }
+    }
+}
+
+impl TransactionLog {
+    fn raw_log(&self) -> Option<RawLog> {
+        let topics = [&self.topic0, &self.topic1, &self.topic2, &self.topic3]
+            .into_iter()
+            .flatten()
+            .map(|topic| H256::from_str(topic).ok())
+            .collect::<Option<Vec<H256>>>()?;
+        let data = hex::decode(&self.data).ok()?;
+
+        Some(RawLog { topics, data })
+    }
+}
+
+/// Log decoded with a registered ABI or one of the standard token events
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedEvent {
+    pub transaction_hash: String,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub address: String,
+    pub event: String,
+    pub signature: String,
+    pub arguments: Vec<DecodedArgument>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub standard: Option<&'static str>,
+    pub source: DecodeSource,
+}
+
+fn standard_events() -> &'static [(&'static str, Event)] {
+    static EVENTS: OnceLock<Vec<(&'static str, Event)>> = OnceLock::new();
+    EVENTS.get_or_init(|| {
+        STANDARD_EVENTS
+            .iter()
+            .map(|(standard, event)| (*standard, HumanReadableParser::parse_event(event).expect("bundled events are valid")))
+            .collect()
+    })
+}
+
+/// `Name(type,...)` of an event, the preimage of its topic
+fn event_signature(event: &Event) -> String {
+    let inputs: Vec<String> = event.inputs.iter().map(|param| param.kind.to_string()).collect();
+    format!("{}({})", event.name, inputs.join(","))
+}
+
+/// Decodes a log with the emitting contract's ABI if there is one, falling back to
+/// the standard token events. Logs that match neither are skipped.
+pub fn decode_log(abi: Option<&Abi>, log: &TransactionLog) -> Option<DecodedEvent> {
+    let raw_log = log.raw_log()?;
+
+    let registered = abi
+        .into_iter()
+        .flat_map(|abi| abi.events())
+        .map(|event| (None, event, DecodeSource::Abi));
+    let standard = standard_events()
+        .iter()
+        .map(|(standard, event)| (Some(*standard), event, DecodeSource::Standard));
+
+    registered.chain(standard).find_map(|(standard, event, source)| {
+        let parsed = event.parse_log_whole(raw_log.clone()).ok()?;
+        let arguments = event.inputs
+            .iter()
+            .zip(parsed.params)
+            .map(|(param, log_param)| DecodedArgument {
+                name: log_param.name,
+                kind: param.kind.to_string(),
+                value: token_to_json(&log_param.value),
+            })
+            .collect();
+
+        Some(DecodedEvent {
+            transaction_hash: log.transaction_hash.clone(),
+            block_number: log.block_number,
+            log_index: log.log_index,
+            address: log.address.clone(),
+            event: event.name.clone(),
+            signature: event_signature(event),
+            arguments,
+            standard,
+            source,
+        })
+    })
+}
+
+/// Decodes the logs of several contracts at once, looking up their registered ABIs in one go
+pub async fn decode_logs(pool: &DBPool, logs: &[TransactionLog]) -> Vec<DecodedEvent> {
+    let mut addresses: Vec<String> = logs.iter().map(|log| log.address.clone()).collect();
+    addresses.sort();
+    addresses.dedup();
+
+    let abis = match get_contract_abis(pool, &addresses).await {
+        Ok(abis) => abis,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch contract ABIs");
+            HashMap::new()
+        }
+    };
+
+    logs.iter().filter_map(|log| decode_log(abis.get(&log.address), log)).collect()
+}
+
+pub fn insert_transaction_logs(conn: &mut PgConnection, logs: &[TransactionLog]) -> Result<usize, diesel::result::Error> {
+    use crate::schema::transaction_logs::dsl::*;
+
+    let mut inserted = 0;
+    for chunk in logs.chunks(MAX_BIND_PARAMS / 9) {
+        inserted += diesel::insert_into(transaction_logs)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(inserted)
+}
+
+/// Only needed to seed tests, lookups store logs along with their transaction
+#[cfg(test)]
+pub async fn store_transaction_logs_in_db(pool: &DBPool, logs: &[TransactionLog]) -> Result<(), diesel::result::Error> {
+    let logs = logs.to_vec();
+    with_conn(pool, move |conn| insert_transaction_logs(conn, &logs).map(|_| ())).await
+}
+
+pub async fn get_transaction_logs(pool: &DBPool, tx_hashes: &[String]) -> Result<Vec<TransactionLog>, diesel::result::Error> {
+    use crate::schema::transaction_logs::dsl::*;
+
+    let tx_hashes = tx_hashes.to_vec();
+    with_conn(pool, move |conn| {
+        transaction_logs
+            .filter(transaction_hash.eq_any(tx_hashes))
+            .order((transaction_hash, log_index))
+            .load::<TransactionLog>(conn)
+    }).await
+}
+
+/// Default and largest page of `/lime/events`
+const DEFAULT_EVENTS_LIMIT: i64 = 100;
+const MAX_EVENTS_LIMIT: i64 = 1000;
+
+/// Position of the last log of a `/lime/events` page, the next page starts
+/// right after it. Written as `<blockNumber>:<transactionHash>:<logIndex>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventCursor {
+    pub block_number: i64,
+    pub transaction_hash: String,
+    pub log_index: i64,
+}
+
+impl EventCursor {
+    fn from_log(log: &TransactionLog) -> Self {
+        EventCursor { block_number: log.block_number, transaction_hash: log.transaction_hash.clone(), log_index: log.log_index }
+    }
+}
+
+impl std::fmt::Display for EventCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.block_number, self.transaction_hash, self.log_index)
+    }
+}
+
+impl FromStr for EventCursor {
+    type Err = ();
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let mut parts = cursor.split(':');
+        let (Some(block_number), Some(transaction_hash), Some(log_index), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(());
+        };
+        Ok(EventCursor {
+            block_number: block_number.parse().map_err(|_| ())?,
+            transaction_hash: format!("{:?}", H256::from_str(transaction_hash).map_err(|_| ())?),
+            log_index: log_index.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// Up to `limit` cached logs of `contract` following `after`, optionally
+/// restricted to some event topics and a block range
+pub async fn find_transaction_logs(
+    pool: &DBPool,
+    contract: &str,
+    topics: Option<&[String]>,
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+    after: Option<&EventCursor>,
+    limit: i64,
+) -> Result<Vec<TransactionLog>, diesel::result::Error> {
+    use crate::schema::transaction_logs::dsl::*;
+
+    let contract = contract.to_string();
+    let topics = topics.map(<[String]>::to_vec);
+    let after = after.cloned();
+    with_conn(pool, move |conn| {
+        let mut query = transaction_logs.filter(address.eq(contract)).into_boxed();
+        if let Some(topics) = topics {
+            query = query.filter(topic0.eq_any(topics));
+        }
+        if let Some(from_block) = from_block {
+            query = query.filter(block_number.ge(from_block));
+        }
+        if let Some(to_block) = to_block {
+            query = query.filter(block_number.le(to_block));
+        }
+        if let Some(after) = after {
+            query = query.filter(
+                block_number.gt(after.block_number).or(block_number.eq(after.block_number).and(
+                    transaction_hash.gt(after.transaction_hash.clone())
+                        .or(transaction_hash.eq(after.transaction_hash).and(log_index.gt(after.log_index))),
+                )),
+            );
+        }
+
+        query
+            .order((block_number, transaction_hash, log_index))
+            .limit(limit)
+            .load::<TransactionLog>(conn)
+    }).await
+}
+
+/// Topics of the events called `name`, either in the contract ABI or among the standard events
+fn event_topics(abi: Option<&Abi>, name: &str) -> Vec<String> {
+    let registered = abi.into_iter().flat_map(|abi| abi.events());
+    let standard = standard_events().iter().map(|(_, event)| event);
+
+    let mut topics: Vec<String> = registered
+        .chain(standard)
+        .filter(|event| event.name == name)
+        .map(|event| format!("{:?}", event.signature()))
+        .collect();
+    topics.sort();
+    topics.dedup();
+    topics
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventQuery {
+    pub address: String,
+    /// Event name (e.g. `Transfer`) or its topic hash
+    pub event: Option<String>,
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// Page size, `DEFAULT_EVENTS_LIMIT` unless set and at most `MAX_EVENTS_LIMIT`
+    pub limit: Option<i64>,
+    /// `nextCursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+#[get("/lime/events")]
+pub async fn lime_events(query: web::Query<EventQuery>, pool: web::Data<DBPool>) -> impl Responder {
+    let EventQuery { address, event, from_block, to_block, limit, cursor } = query.into_inner();
+
+    let limit = match limit {
+        None => DEFAULT_EVENTS_LIMIT,
+        Some(limit) if limit > 0 => limit.min(MAX_EVENTS_LIMIT),
+        Some(_) => return HttpResponse::BadRequest().body("Invalid Limit"),
+    };
+    let cursor = match cursor.as_deref().map(EventCursor::from_str).transpose() {
+        Ok(cursor) => cursor,
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Cursor"),
+    };
+
+    let Ok(address) = Address::from_str(&address).map(|address| format!("{:?}", address)) else {
+        return HttpResponse::BadRequest().body("Invalid Contract Address");
+    };
+
+    let topics = match event.as_deref() {
+        None => None,
+        Some(topic) if topic.starts_with("0x") => match H256::from_str(topic) {
+            Ok(topic) => Some(vec![format!("{:?}", topic)]),
+            Err(_) => return HttpResponse::BadRequest().body("Invalid Event Topic"),
+        },
+        Some(name) => {
+            let abis = get_contract_abis(&pool, std::slice::from_ref(&address)).await.unwrap_or_default();
+            let topics = event_topics(abis.get(&address), name);
+            if topics.is_empty() {
+                return HttpResponse::BadRequest().body(format!("Unknown event: {}", name));
+            }
+            Some(topics)
+        }
+    };
+
+    // One more than the page to tell whether another one follows
+    match find_transaction_logs(&pool, &address, topics.as_deref(), from_block, to_block, cursor.as_ref(), limit + 1).await {
+        Ok(mut logs) => {
+            let next_cursor = if logs.len() as i64 > limit {
+                logs.truncate(limit as usize);
+                logs.last().map(|log| EventCursor::from_log(log).to_string())
+            } else {
+                None
+            };
+            let mut events = decode_logs(&pool, &logs).await;
+            // Same topic doesn't always mean same event name across ABIs
+            if let Some(name) = event.as_deref().filter(|event| !event.starts_with("0x")) {
+                events.retain(|decoded| decoded.event == name);
+            }
+            HttpResponse::Ok().json(serde_json::json!({ "events": events, "nextCursor": next_cursor }))
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to fetch transaction logs");
+            HttpResponse::InternalServerError().body("Failed to fetch events")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::parse_abi;
+
+    const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+    fn log(topics: &[&str], data: &str) -> TransactionLog {
+        let topic = |index: usize| topics.get(index).map(|topic| topic.to_string());
+        TransactionLog {
+            transaction_hash: format!("{:?}", H256::repeat_byte(0x01)),
+            log_index: 0,
+            block_number: 1,
+            address: "0x3333333333333333333333333333333333333333".to_string(),
+            topic0: topic(0),
+            topic1: topic(1),
+            topic2: topic(2),
+            topic3: topic(3),
+            data: data.to_string(),
+        }
+    }
+
+    const FROM_TOPIC: &str = "0x0000000000000000000000001111111111111111111111111111111111111111";
+    const TO_TOPIC: &str = "0x0000000000000000000000002222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_decode_log_erc20_transfer() {
+        let log = log(&[TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC], "0x00000000000000000000000000000000000000000000000000000000000003e8");
+        let decoded = decode_log(None, &log).unwrap();
+        assert_eq!(decoded.event, "Transfer");
+        assert_eq!(decoded.standard, Some("ERC-20"));
+        assert_eq!(decoded.source, DecodeSource::Standard);
+        assert_eq!(decoded.arguments[0].value, "0x1111111111111111111111111111111111111111");
+        assert_eq!(decoded.arguments[2].name, "value");
+        assert_eq!(decoded.arguments[2].value, "1000");
+    }
+
+    #[test]
+    fn test_decode_log_erc721_transfer() {
+        let token_id = "0x0000000000000000000000000000000000000000000000000000000000000007";
+        let log = log(&[TRANSFER_TOPIC, FROM_TOPIC, TO_TOPIC, token_id], "0x");
+        let decoded = decode_log(None, &log).unwrap();
+        assert_eq!(decoded.standard, Some("ERC-721"));
+        assert_eq!(decoded.arguments[2].name, "tokenId");
+        assert_eq!(decoded.arguments[2].value, "7");
+    }
+
+    #[test]
+    fn test_decode_log_registered_abi() {
+        let abi = parse_abi(&["event Deposit(address indexed dst, uint256 wad)"]).unwrap();
+        let topic = format!("{:?}", abi.event("Deposit").unwrap().signature());
+        let log = log(&[&topic, FROM_TOPIC], "0x00000000000000000000000000000000000000000000000000000000000003e8");
+
+        assert_eq!(decode_log(None, &log), None);
+        let decoded = decode_log(Some(&abi), &log).unwrap();
+        assert_eq!(decoded.event, "Deposit");
+        assert_eq!(decoded.signature, "Deposit(address,uint256)");
+        assert_eq!(decoded.source, DecodeSource::Abi);
+    }
+
+    #[test]
+    fn test_event_topics() {
+        assert_eq!(event_topics(None, "Transfer"), vec![TRANSFER_TOPIC.to_string()]);
+        assert!(event_topics(None, "Unknown").is_empty());
+    }
+
+    #[test]
+    fn test_event_cursor() {
+        let cursor = EventCursor { block_number: 100, transaction_hash: format!("{:?}", H256::repeat_byte(0x55)), log_index: 2 };
+        assert_eq!(EventCursor::from_str(&cursor.to_string()), Ok(cursor));
+        assert!(EventCursor::from_str("100:0x55:2").is_err());
+        assert!(EventCursor::from_str("100:2").is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::error;
+
+use crate::DBPool;
+use crate::response::{OutputFormat, ResponseOptions};
+use crate::transaction::{Transaction, get_transactions_page_from_db};
+use crate::users::get_user_search_transactions_page;
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Rows read from Postgres per query while streaming an export
+const EXPORT_CHUNK_SIZE: i64 = 1000;
+
+/// Fields of a `Transaction`, in the order of the CSV columns
+pub const TRANSACTION_COLUMNS: &[&str] = &[
+    "transactionHash",
+    "transactionStatus",
+    "blockHash",
+    "blockNumber",
+    "from",
+    "to",
+    "contractAddress",
+    "logsCount",
+    "input",
+    "value",
+];
+
+/// `columns` query parameter, a comma separated subset of `TRANSACTION_COLUMNS`
+#[derive(Debug, Deserialize)]
+pub struct ColumnParams {
+    pub columns: Option<String>,
+}
+
+pub fn columns_from_request(req: &HttpRequest) -> Result<Vec<&'static str>, String> {
+    let columns = web::Query::<ColumnParams>::from_query(req.query_string())
+        .ok()
+        .and_then(|params| params.into_inner().columns);
+    parse_columns(columns.as_deref())
+}
+
+/// All columns when unset, unknown columns are an error
+pub fn parse_columns(columns: Option<&str>) -> Result<Vec<&'static str>, String> {
+    let Some(columns) = columns else {
+        return Ok(TRANSACTION_COLUMNS.to_vec());
+    };
+
+    columns
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !column.is_empty())
+        .map(|column| {
+            TRANSACTION_COLUMNS
+                .iter()
+                .find(|known| **known == column)
+                .copied()
+                .ok_or_else(|| format!("Unknown column: {}", column))
+        })
+        .collect()
+}
+
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => return String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+pub fn csv_header(columns: &[&str]) -> String {
+    format!("{}\n", columns.join(","))
+}
+
+/// Renders transactions as CSV lines or NDJSON objects restricted to `columns`,
+/// after applying the response options
+pub fn render_rows(format: &OutputFormat, columns: &[&str], options: &ResponseOptions, transactions: &[Transaction]) -> String {
+    let mut out = String::new();
+    for transaction in transactions {
+        let row = options.format_transaction(transaction);
+        match format {
+            OutputFormat::Csv => {
+                let fields: Vec<String> = columns.iter().map(|column| csv_field(&row[*column])).collect();
+                out.push_str(&fields.join(","));
+            }
+            _ => {
+                let object: serde_json::Map<String, Value> = columns
+                    .iter()
+                    .map(|column| (column.to_string(), row[*column].clone()))
+                    .collect();
+                out.push_str(&Value::Object(object).to_string());
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn content_type(format: &OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Csv => CSV_CONTENT_TYPE,
+        _ => NDJSON_CONTENT_TYPE,
+    }
+}
+
+/// Which transactions an export walks through
+#[derive(Debug, Clone)]
+pub enum ExportSource {
+    All,
+    UserSearches(String),
+}
+
+impl ExportSource {
+    async fn load_chunk(&self, pool: &DBPool, after: Option<&str>) -> Result<Vec<Transaction>, diesel::result::Error> {
+        match self {
+            ExportSource::All => get_transactions_page_from_db(pool, after, EXPORT_CHUNK_SIZE).await,
+            ExportSource::UserSearches(username) => get_user_search_transactions_page(pool, username, after, EXPORT_CHUNK_SIZE).await,
+        }
+    }
+}
+
+struct ExportState {
+    pool: DBPool,
+    source: ExportSource,
+    format: OutputFormat,
+    columns: Vec<&'static str>,
+    options: ResponseOptions,
+    /// Hash of the last row sent, `None` before the first chunk
+    after: Option<String>,
+    done: bool,
+}
+
+/// Streams the transactions of `source` as CSV or NDJSON, reading them in
+/// chunks ordered by hash so memory use doesn't grow with the table
+pub fn export_response(req: &HttpRequest, pool: &DBPool, source: ExportSource, format: OutputFormat) -> HttpResponse {
+    let options = match ResponseOptions::from_request(req) {
+        Ok(options) => options,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let columns = match columns_from_request(req) {
+        Ok(columns) => columns,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let header = match format {
+        OutputFormat::Csv => csv_header(&columns),
+        _ => String::new(),
+    };
+    let content_type = content_type(&format);
+    let state = ExportState { pool: pool.clone(), source, format, columns, options, after: None, done: false };
+
+    let rows = stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        let chunk = match state.source.load_chunk(&state.pool, state.after.as_deref()).await {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                error!(error = %e, "Failed to export transactions");
+                return Some((Err(actix_web::error::ErrorInternalServerError("Failed to export transactions")), state));
+            }
+        };
+        if chunk.len() < EXPORT_CHUNK_SIZE as usize {
+            state.done = true;
+        }
+        if chunk.is_empty() {
+            return None;
+        }
+
+        state.after = chunk.last().map(|tx| tx.transaction_hash.clone());
+        let body = render_rows(&state.format, &state.columns, &state.options, &chunk);
+        Some((Ok(web::Bytes::from(body)), state))
+    });
+    let body = stream::once(async move { Ok::<_, actix_web::Error>(web::Bytes::from(header)) }).chain(rows);
+
+    HttpResponse::Ok().content_type(content_type).streaming(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_transaction;
+
+    fn transaction() -> Transaction {
+        Transaction {
+            contract_address: Some("0x2222222222222222222222222222222222222222".to_string()),
+            logs_count: 1,
+            value: "1000".to_string(),
+            ..test_transaction("0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e", "0xabababababababababababababababababababababababababababababababab", 5703601)
+        }
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(parse_columns(None).unwrap(), TRANSACTION_COLUMNS);
+        assert_eq!(parse_columns(Some("value, transactionHash")).unwrap(), vec!["value", "transactionHash"]);
+        assert!(parse_columns(Some("value,gasPrice")).is_err());
+    }
+
+    #[test]
+    fn test_render_rows_csv() {
+        let columns = parse_columns(Some("transactionHash,to,blockNumber,transactionStatus,value")).unwrap();
+        let csv = render_rows(&OutputFormat::Csv, &columns, &ResponseOptions::default(), &[transaction()]);
+        assert_eq!(csv, "0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e,,5703601,true,1000\n");
+        assert_eq!(csv_header(&columns), "transactionHash,to,blockNumber,transactionStatus,value\n");
+    }
+
+    #[test]
+    fn test_render_rows_ndjson() {
+        let columns = parse_columns(Some("blockNumber,contractAddress")).unwrap();
+        let ndjson = render_rows(&OutputFormat::Ndjson, &columns, &ResponseOptions::default(), &[transaction(), transaction()]);
+        let lines: Vec<&str> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let row: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(row, serde_json::json!({ "blockNumber": 5703601, "contractAddress": "0x2222222222222222222222222222222222222222" }));
+    }
+
+    #[test]
+    fn test_csv_field_escaping() {
+        assert_eq!(csv_field(&Value::String("a,b".into())), "\"a,b\"");
+        assert_eq!(csv_field(&Value::String("say \"hi\"".into())), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field(&Value::Null), "");
+        assert_eq!(csv_field(&Value::Bool(false)), "false");
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use ethers::providers::Middleware;
+use ethers::types::U256;
+use serde::Serialize;
+
+use crate::DBPool;
+use crate::setup::pending_migrations;
+use crate::config::Config;
+use crate::transaction::EthProvider;
+
+/// How long a readiness check waits for a connection or the node before failing
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Ordered from best to worst, a readiness is as bad as its worst check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Failing, but the server keeps serving what it can without it
+    Degraded,
+    Error,
+}
+
+/// Outcome of checking a single dependency
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyCheck {
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl DependencyCheck {
+    fn new(started: Instant, result: Result<Option<String>, String>) -> Self {
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok(detail) => DependencyCheck { status: CheckStatus::Ok, latency_ms, detail },
+            Err(e) => DependencyCheck { status: CheckStatus::Error, latency_ms, detail: Some(e) },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessChecks {
+    pub database: DependencyCheck,
+    pub migrations: DependencyCheck,
+    pub eth_node: DependencyCheck,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: CheckStatus,
+    pub checks: ReadinessChecks,
+}
+
+/// Checks out a connection and, with it, whether all migrations were applied
+fn check_database_blocking(pool: &DBPool) -> (DependencyCheck, DependencyCheck) {
+    let started = Instant::now();
+    let mut conn = match pool.get_timeout(CHECK_TIMEOUT) {
+        Ok(conn) => conn,
+        Err(e) => {
+            let database = DependencyCheck::new(started, Err(e.to_string()));
+            let migrations = DependencyCheck::new(Instant::now(), Err("No database connection".to_string()));
+            return (database, migrations);
+        }
+    };
+    let database = DependencyCheck::new(started, Ok(None));
+
+    let started = Instant::now();
+    let result = pending_migrations(&mut conn).and_then(|pending| match pending {
+        0 => Ok(None),
+        pending => Err(format!("{} pending migrations", pending)),
+    });
+    (database, DependencyCheck::new(started, result))
+}
+
+/// `check_database_blocking` on the blocking thread pool, like every other query
+async fn check_database(pool: &DBPool) -> (DependencyCheck, DependencyCheck) {
+    let started = Instant::now();
+    let pool = pool.clone();
+    web::block(move || check_database_blocking(&pool)).await.unwrap_or_else(|e| {
+        let database = DependencyCheck::new(started, Err(e.to_string()));
+        let migrations = DependencyCheck::new(Instant::now(), Err("No database connection".to_string()));
+        (database, migrations)
+    })
+}
+
+/// Asks the node for `eth_chainId` and compares it with `eth.expected_chain_id`
+async fn check_eth_node(config: &Config, provider: &EthProvider) -> DependencyCheck {
+    let started = Instant::now();
+    let result = match actix_web::rt::time::timeout(CHECK_TIMEOUT, provider.get_chainid()).await {
+        Err(_) => Err("Timed out waiting for eth_chainId".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Ok(Ok(chain_id)) => match config.eth.expected_chain_id {
+            Some(expected) if U256::from(expected) != chain_id => Err(format!("Chain id {} doesn't match the expected {}", chain_id, expected)),
+            _ => Ok(Some(format!("Chain id {}", chain_id))),
+        },
+    };
+    DependencyCheck::new(started, result)
+}
+
+pub async fn readiness(pool: &DBPool, config: &Config, provider: &EthProvider) -> Readiness {
+    let (mut database, mut migrations) = check_database(pool).await;
+    let eth_node = check_eth_node(config, provider).await;
+
+    // Lookups are still answered from the node while the database is down
+    if config.database.degraded_mode {
+        for check in [&mut database, &mut migrations] {
+            if check.status == CheckStatus::Error {
+                check.status = CheckStatus::Degraded;
+            }
+        }
+    }
+
+    let status = [&database, &migrations, &eth_node].iter().map(|check| check.status).max().unwrap_or(CheckStatus::Ok);
+    Readiness { status, checks: ReadinessChecks { database, migrations, eth_node } }
+}
+
+/// Liveness, answers as long as the server is able to handle requests
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": CheckStatus::Ok }))
+}
+
+/// Readiness, `503` unless the database, its migrations and the ETH node are all fine.
+/// In degraded mode a failing database is reported as `degraded` and only the node counts.
+#[get("/readyz")]
+pub async fn readyz(pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>) -> impl Responder {
+    let readiness = readiness(&pool, &config, &provider).await;
+    match readiness.status {
+        CheckStatus::Ok | CheckStatus::Degraded => HttpResponse::Ok().json(readiness),
+        CheckStatus::Error => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dependency_check_serialization() {
+        let check = DependencyCheck::new(Instant::now(), Err("down".to_string()));
+        let json = serde_json::to_value(&check).unwrap();
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["detail"], "down");
+        assert!(json["latencyMs"].is_number());
+
+        let check = DependencyCheck::new(Instant::now(), Ok(None));
+        assert!(serde_json::to_value(&check).unwrap().get("detail").is_none());
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use ethers::types::{Address, H256, U256};
+use ethers::utils::hex;
+
+use crate::DBPool;
+use crate::db::unavailable;
+use crate::export::TRANSACTION_COLUMNS;
+use crate::transaction::{Transaction, insert_transactions};
+
+const DEFAULT_IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Invalid records reported one by one before only being counted
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// A JSON array of transactions, or a `{"transactions": [...]}` object as returned by `/lime/all`
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl ImportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ImportFormat::Json),
+            "ndjson" | "jsonl" => Some(ImportFormat::Ndjson),
+            "csv" => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+}
+
+/// Outcome of an import. `skipped` transactions were already cached.
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub inserted: usize,
+    pub skipped: usize,
+    pub invalid: usize,
+    /// `(record number, reason)` of the first invalid records
+    pub errors: Vec<(usize, String)>,
+}
+
+impl ImportReport {
+    fn invalid(&mut self, record: usize, reason: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push((record, reason));
+        }
+    }
+}
+
+/// CSV field as the JSON value the `Transaction` field expects
+fn csv_value(column: &str, field: &str) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    match column {
+        "transactionStatus" => match field {
+            "true" | "1" => Value::Bool(true),
+            "false" | "0" => Value::Bool(false),
+            _ => Value::String(field.to_string()),
+        },
+        "blockNumber" | "logsCount" => field.parse::<i64>().map(Value::from).unwrap_or_else(|_| Value::String(field.to_string())),
+        _ => Value::String(field.to_string()),
+    }
+}
+
+/// Records of a dump, one JSON object per transaction
+type Records<'a> = Box<dyn Iterator<Item = Result<Value, String>> + 'a>;
+
+/// Reads the dump as one JSON object per transaction, keeping unparsable
+/// records as errors so they're reported along with the invalid transactions.
+/// NDJSON and CSV are read record by record, a JSON array is read whole.
+pub fn parse_records<'a, R: BufRead + 'a>(input: R, format: ImportFormat) -> Result<Records<'a>, String> {
+    match format {
+        ImportFormat::Json => {
+            let value: Value = serde_json::from_reader(input).map_err(|e| format!("Invalid JSON: {}", e))?;
+            let records = match value {
+                Value::Array(records) => records,
+                Value::Object(mut object) => match object.remove("transactions") {
+                    Some(Value::Array(records)) => records,
+                    _ => return Err("Expected an array of transactions".to_string()),
+                },
+                _ => return Err("Expected an array of transactions".to_string()),
+            };
+            Ok(Box::new(records.into_iter().map(Ok)))
+        }
+        ImportFormat::Ndjson => Ok(Box::new(input
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|line| {
+                let line = line.map_err(|e| format!("Couldn't read the line: {}", e))?;
+                serde_json::from_str(&line).map_err(|e| format!("Invalid JSON: {}", e))
+            }))),
+        ImportFormat::Csv => {
+            // Field counts are checked per record, so a short record is reported rather than aborting the import
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+            let header: Vec<String> = reader.headers().map_err(|e| format!("Invalid CSV: {}", e))?.iter().map(str::to_string).collect();
+            if header.is_empty() {
+                return Err("Missing CSV header".to_string());
+            }
+            if let Some(unknown) = header.iter().find(|column| !TRANSACTION_COLUMNS.contains(&column.as_str())) {
+                return Err(format!("Unknown column: {}", unknown));
+            }
+
+            Ok(Box::new(reader
+                .into_records()
+                .filter(|record| !record.as_ref().is_ok_and(|fields| fields.iter().all(str::is_empty)))
+                .map(move |record| {
+                    let fields = record.map_err(|e| format!("Invalid CSV: {}", e))?;
+                    if fields.len() != header.len() {
+                        return Err(format!("Expected {} fields, found {}", header.len(), fields.len()));
+                    }
+                    let object = header
+                        .iter()
+                        .zip(fields.iter())
+                        .map(|(column, field)| (column.clone(), csv_value(column, field)))
+                        .collect();
+                    Ok(Value::Object(object))
+                })))
+        }
+    }
+}
+
+fn normalize_hash(hash: &str, field: &str) -> Result<String, String> {
+    if hash.len() != 66 {
+        return Err(format!("Invalid {}: {}", field, hash));
+    }
+    H256::from_str(hash).map(|hash| format!("{:?}", hash)).map_err(|_| format!("Invalid {}: {}", field, hash))
+}
+
+fn normalize_address(address: &str, field: &str) -> Result<String, String> {
+    if address.len() != 42 {
+        return Err(format!("Invalid {}: {}", field, address));
+    }
+    Address::from_str(address).map(|address| format!("{:?}", address)).map_err(|_| format!("Invalid {}: {}", field, address))
+}
+
+/// Checks hashes, addresses and amounts of an imported transaction and brings
+/// them into the form the fetcher stores (lowercase hex, decimal wei)
+pub fn validate_transaction(transaction: Transaction) -> Result<Transaction, String> {
+    if transaction.block_number < 0 || transaction.logs_count < 0 {
+        return Err("Negative blockNumber or logsCount".to_string());
+    }
+    if hex::decode(&transaction.input).is_err() {
+        return Err(format!("Invalid input: {}", transaction.input));
+    }
+    let value = U256::from_dec_str(&transaction.value).map_err(|_| format!("Invalid value: {}", transaction.value))?;
+
+    Ok(Transaction {
+        transaction_hash: normalize_hash(&transaction.transaction_hash, "transactionHash")?,
+        block_hash: normalize_hash(&transaction.block_hash, "blockHash")?,
+        from: normalize_address(&transaction.from, "from")?,
+        to: transaction.to.as_deref().map(|to| normalize_address(to, "to")).transpose()?,
+        contract_address: transaction.contract_address.as_deref().map(|address| normalize_address(address, "contractAddress")).transpose()?,
+        input: transaction.input.to_lowercase(),
+        value: value.to_string(),
+        ..transaction
+    })
+}
+
+/// Inserts a batch, transactions already cached are left untouched. Returns how many were new.
+/// Batches larger than a statement allows are split by `insert_transactions`.
+pub fn insert_transactions_batch(pool: &DBPool, batch: &[Transaction]) -> Result<usize, diesel::result::Error> {
+    let mut conn = pool.get().map_err(|e| unavailable(e.to_string()))?;
+    insert_transactions(&mut conn, batch)
+}
+
+/// Streams the dump into the database, `batch_size` transactions per insert
+pub fn import_transactions<R: BufRead>(pool: &DBPool, input: R, format: ImportFormat, batch_size: usize) -> Result<ImportReport, String> {
+    let mut report = ImportReport::default();
+    let mut batch: Vec<Transaction> = Vec::with_capacity(batch_size);
+
+    let flush = |batch: &mut Vec<Transaction>, report: &mut ImportReport| -> Result<(), String> {
+        let inserted = insert_transactions_batch(pool, batch).map_err(|e| format!("Failed to insert transactions: {}", e))?;
+        report.inserted += inserted;
+        report.skipped += batch.len() - inserted;
+        batch.clear();
+        Ok(())
+    };
+
+    for (index, record) in parse_records(input, format)?.enumerate() {
+        let transaction = record
+            .and_then(|value| serde_json::from_value::<Transaction>(value).map_err(|e| e.to_string()))
+            .and_then(validate_transaction);
+        match transaction {
+            Ok(transaction) => batch.push(transaction),
+            Err(e) => report.invalid(index + 1, e),
+        }
+
+        if batch.len() >= batch_size {
+            flush(&mut batch, &mut report)?;
+        }
+    }
+    if !batch.is_empty() {
+        flush(&mut batch, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+/// `import <file> [--format json|ndjson|csv] [--batch-size <n>]`, the format
+/// defaults to the file extension
+pub fn run_import_command(pool: &DBPool, args: &[String]) -> Result<(), String> {
+    let mut path: Option<&Path> = None;
+    let mut format: Option<ImportFormat> = None;
+    let mut batch_size = DEFAULT_IMPORT_BATCH_SIZE;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let name = args.next().ok_or("Missing value for --format")?;
+                format = Some(ImportFormat::from_name(name).ok_or_else(|| format!("Unsupported format: {}", name))?);
+            }
+            "--batch-size" => {
+                let value = args.next().ok_or("Missing value for --batch-size")?;
+                batch_size = value.parse().ok().filter(|size| *size > 0).ok_or_else(|| format!("Invalid batch size: {}", value))?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            file => path = Some(Path::new(file)),
+        }
+    }
+
+    let path = path.ok_or("Missing file to import")?;
+    let format = format
+        .or_else(|| ImportFormat::from_path(path))
+        .ok_or("Unknown file format, use --format json|ndjson|csv")?;
+    let input = File::open(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+
+    let report = import_transactions(pool, BufReader::new(input), format, batch_size)?;
+    for (record, reason) in &report.errors {
+        eprintln!("Record {}: {}", record, reason);
+    }
+    println!("inserted: {}, skipped: {}, invalid: {}", report.inserted, report.skipped, report.invalid);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_transaction;
+
+    const HASH: &str = "0x22F4A0E8243F6BECD1E4E31FCE147244A5DE7CE604080854C516B324A186A59E";
+
+    #[test]
+    fn test_parse_records_csv() {
+        let csv = format!("transactionHash,transactionStatus,blockHash,blockNumber,from,to,contractAddress,logsCount,input,value\n\
+            {},true,{},5703601,0x1111111111111111111111111111111111111111,,,0,0x,1000\n\
+            {},1,{}\n", HASH, HASH, HASH, HASH);
+        let records: Vec<_> = parse_records(csv.as_bytes(), ImportFormat::Csv).unwrap().collect();
+        assert_eq!(records.len(), 2);
+
+        let transaction: Transaction = serde_json::from_value(records[0].clone().unwrap()).unwrap();
+        assert_eq!(transaction.to, None);
+        assert_eq!(transaction.block_number, 5703601);
+        assert!(records[1].is_err());
+
+        assert!(parse_records("transactionHash,gasPrice\n".as_bytes(), ImportFormat::Csv).is_err());
+
+        // Quoted fields may hold commas, escaped quotes and line breaks
+        let csv = "transactionHash,input,value\r\n\"0x,\"\"1\"\"\",0x,\n\n\"multi\nline\",0x,3";
+        let records: Vec<_> = parse_records(csv.as_bytes(), ImportFormat::Csv).unwrap().map(Result::unwrap).collect();
+        assert_eq!(records[0]["transactionHash"], "0x,\"1\"");
+        assert_eq!(records[0]["value"], Value::Null);
+        assert_eq!(records[1]["transactionHash"], "multi\nline");
+    }
+
+    #[test]
+    fn test_parse_records_json() {
+        let json = r#"{"transactions": [{"transactionHash": "0x01"}, {}]}"#;
+        assert_eq!(parse_records(json.as_bytes(), ImportFormat::Json).unwrap().count(), 2);
+        assert!(parse_records(r#"{"other": []}"#.as_bytes(), ImportFormat::Json).is_err());
+
+        let ndjson = "{\"a\": 1}\n\nnot json\n";
+        let records: Vec<_> = parse_records(ndjson.as_bytes(), ImportFormat::Ndjson).unwrap().collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[1].is_err());
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            from: "0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            to: Some("0x2222222222222222222222222222222222222222".to_string()),
+            input: "0xA9059CBB".to_string(),
+            value: "1000".to_string(),
+            ..test_transaction(HASH, HASH, 1)
+        }
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let validated = validate_transaction(transaction()).unwrap();
+        assert_eq!(validated.transaction_hash, HASH.to_lowercase());
+        assert_eq!(validated.from, "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(validated.input, "0xa9059cbb");
+
+        assert!(validate_transaction(Transaction { transaction_hash: "0x1234".to_string(), ..transaction() }).is_err());
+        assert!(validate_transaction(Transaction { to: Some("0x12".to_string()), ..transaction() }).is_err());
+        assert!(validate_transaction(Transaction { from: "0xzz11111111111111111111111111111111111111".to_string(), ..transaction() }).is_err());
+        assert!(validate_transaction(Transaction { value: "-1".to_string(), ..transaction() }).is_err());
+        assert!(validate_transaction(Transaction { input: "0xabc".to_string(), ..transaction() }).is_err());
+        assert!(validate_transaction(Transaction { block_number: -1, ..transaction() }).is_err());
+    }
+}
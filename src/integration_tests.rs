@@ -1,15 +1,26 @@
 #[cfg(test)]
 mod tests {
     use crate::DBPool;
+    use crate::config::Config;
     use crate::auth::{authenticate, AuthData};
+    use crate::abi::{register_abi, unregister_abi};
+    use crate::analytics::lime_export_parquet;
+    use crate::cache::TransactionCache;
+    use crate::blocks::{lime_block, store_block_in_db, Block};
+    use crate::prices::{CsvPriceSource, load_prices};
+    use crate::events::{lime_events, store_transaction_logs_in_db, TransactionLog};
     use crate::routes::{
         lime_eth_transactions_hashes,
         lime_all,
         lime_eth_rlphex,
+        lime_eth_batch,
         lime_my
     };
-    use crate::transaction::Transaction;
+    use crate::tokens::{lime_address_tokens, store_token_transfers_in_db, TokenMetadata, TokenTransfer};
+    use crate::transaction::{EthProvider, Transaction, decode_transactions_rlp, store_transaction_in_db, test_transaction};
     use crate::setup;
+    use crate::schema::users;
+    use crate::users::{NewUser, hash_password};
 
     use actix_web::{test, App};
     use actix_web::web::Data;
@@ -19,13 +30,43 @@ mod tests {
     use diesel::r2d2::{self, ConnectionManager};
     use serde_json::Value;
     use std::env;
+    use std::time::Duration;
 
     // Ran only once in order to setup the Test database
     #[ctor]
     fn run_db_migrations() {
         let pool = setup_test_db();
         let mut conn = pool.get().expect("Failed to get connection from pool.");
-        setup::run_migrations(&mut conn);
+        setup::run_migrations(&mut conn).expect("Failed to run migrations.");
+
+        // Each with their name as password
+        for name in ["alice", "bob", "carol", "dave"] {
+            let user = NewUser { username: name.to_string(), password_hash: hash_password(name).unwrap() };
+            diesel::insert_into(users::table).values(&user).on_conflict_do_nothing().execute(&mut conn).expect("Failed to add test users.");
+        }
+    }
+
+    /// Configuration from the environment, with a JWT secret for the tests
+    fn test_config() -> Data<Config> {
+        let mut config = Config::default();
+        config.auth.jwt_secret = "test_secret".to_string();
+        config.auth.admin_users = vec!["alice".to_string()];
+        config.eth.node_url = "http://127.0.0.1:8545".to_string();
+        let errors = config.apply_env(|name| env::var(name).ok());
+        assert!(errors.is_empty(), "{:?}", errors);
+        Data::new(config)
+    }
+
+    fn test_provider() -> Data<EthProvider> {
+        Data::new(test_config().eth_provider().expect("ETH_NODE_URL must be a valid URL"))
+    }
+
+    fn test_prices() -> Data<CsvPriceSource> {
+        Data::new(CsvPriceSource::default())
+    }
+
+    fn test_cache() -> Data<TransactionCache> {
+        Data::new(TransactionCache::new(100, Duration::from_secs(60), Duration::from_secs(60), 0))
     }
 
     fn setup_test_db() -> DBPool {
@@ -43,7 +84,7 @@ mod tests {
         let pool = setup_test_db();
 
         // Set up test server
-        let mut app = test::init_service(App::new().app_data(Data::new(pool.clone())).service(lime_eth_transactions_hashes).service(lime_all)).await;
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).app_data(test_cache()).service(lime_eth_transactions_hashes).service(lime_all)).await;
 
         // Gathered transaction from Sepolia-etherscan: https://sepolia.etherscan.io/tx/0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e
         // test purposes only - 0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e
@@ -54,12 +95,28 @@ mod tests {
         let req = test::TestRequest::get()
             .uri(&uri)
             .to_request();
-        let resp: Value = test::call_and_read_body_json(&mut app, req).await;
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
         let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
         assert!(!transactions.is_empty(), "No transactions found");
 
         let transaction = transactions.first().unwrap();
         assert_eq!(transaction.transaction_hash, example_transaction_hash);
+
+        // Same transaction with the value in ether and checksummed addresses
+        let req = test::TestRequest::get()
+            .uri(&format!("{}&unit=ether&addresses=checksum", uri))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let formatted = &resp["transactions"][0];
+        let value = ethers::utils::format_units(ethers::types::U256::from_dec_str(&transaction.value).unwrap(), "ether").unwrap();
+        assert_eq!(formatted["value"].as_str().unwrap(), value.trim_end_matches('0').trim_end_matches('.'));
+        let from: ethers::types::Address = transaction.from.parse().unwrap();
+        assert_eq!(formatted["from"], ethers::utils::to_checksum(&from, None));
+
+        let req = test::TestRequest::get()
+            .uri(&format!("{}&unit=finney", uri))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
     }
 
     #[actix_web::test]
@@ -67,7 +124,7 @@ mod tests {
         let pool = setup_test_db();
 
         // Set up test server
-        let mut app = test::init_service(App::new().app_data(Data::new(pool.clone())).service(lime_eth_rlphex)).await;
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).app_data(test_cache()).service(lime_eth_rlphex)).await;
 
         // Gathered transaction from Sepolia-etherscan
         let first_transaction_hash = "0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542";
@@ -80,7 +137,7 @@ mod tests {
         let req = test::TestRequest::get()
             .uri(&uri)
             .to_request();
-        let resp: Value = test::call_and_read_body_json(&mut app, req).await;
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
 
         let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
         assert!(!transactions.is_empty(), "No transactions found");
@@ -89,12 +146,82 @@ mod tests {
         assert_eq!(transaction.transaction_hash, first_transaction_hash);
     }
 
+    #[actix_web::test]
+    async fn test_lime_eth_rlp_format() {
+        let pool = setup_test_db();
+
+        // Set up test server
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).app_data(test_cache()).service(lime_eth_rlphex)).await;
+
+        let first_transaction_hash = "0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542";
+        let rlp_hex = "0xe1a06d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542";
+
+        // Selected via `Accept`
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/eth/{}", rlp_hex))
+            .insert_header(("Accept", "application/x-rlp"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let transactions = decode_transactions_rlp(&body).expect("Failed to decode RLP response");
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].transaction_hash, first_transaction_hash);
+
+        // Selected via `format`
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/eth/{}?format=rlp", rlp_hex))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let transactions = decode_transactions_rlp(&body).expect("Failed to decode RLP response");
+        assert_eq!(transactions[0].transaction_hash, first_transaction_hash);
+    }
+
+    #[actix_web::test]
+    async fn test_lime_eth_batch() {
+        let pool = setup_test_db();
+
+        // Set up test server
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).app_data(test_cache()).service(lime_eth_batch)).await;
+
+        let first_transaction_hash = "0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542";
+        let second_transaction_hash = "0x2f5bf15391119b4851c619f97d15ec9c0bd580eb034c29ce32e4c35bb3f288eb";
+
+        // JSON array of hashes
+        let req = test::TestRequest::post()
+            .uri("/lime/eth/batch")
+            .set_json(vec![first_transaction_hash, second_transaction_hash])
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].transaction_hash, first_transaction_hash);
+
+        // Raw RLP bytes
+        let rlp_bytes = ethers::utils::hex::decode("e1a06d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542").unwrap();
+        let req = test::TestRequest::post()
+            .uri("/lime/eth/batch")
+            .insert_header(ContentType::octet_stream())
+            .set_payload(rlp_bytes)
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].transaction_hash, first_transaction_hash);
+
+        // Invalid hash
+        let req = test::TestRequest::post()
+            .uri("/lime/eth/batch")
+            .set_json(vec!["0x1234"])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+
     #[actix_web::test]
     async fn test_lime_all() {
         let pool = setup_test_db();
 
         // Set up test server
-        let mut app = test::init_service(App::new().app_data(Data::new(pool.clone())).service(lime_eth_transactions_hashes).service(lime_all)).await;
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).app_data(test_cache()).service(lime_eth_transactions_hashes).service(lime_all)).await;
 
         // Gathered transaction from Sepolia-etherscan: https://sepolia.etherscan.io/tx/0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e
         // test purposes only - 0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e
@@ -106,20 +233,57 @@ mod tests {
             .uri(&format!("/lime/eth?transactionHashes={}&transactionHashes={}&transactionHashes={}",
                     sample_tx_hash, sample_tx_hash, sample_tx_hash_1))
             .to_request();
-        test::call_service(&mut app, req).await;
+        test::call_service(&app, req).await;
 
         let req = test::TestRequest::get()
             .uri("/lime/all")
             .to_request();
-        let resp: Value = test::call_and_read_body_json(&mut app, req).await;
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
         let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
 
         assert!(transactions.len() >= 2, "Transactions stored are less than expected");
+
+        // Streamed CSV export with a subset of the columns
+        let req = test::TestRequest::get()
+            .uri("/lime/all?format=csv&columns=transactionHash,blockNumber")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv");
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        let mut lines = body.lines();
+        assert_eq!(lines.next(), Some("transactionHash,blockNumber"));
+        assert!(lines.any(|line| line.starts_with(&format!("{},", sample_tx_hash))));
+
+        let req = test::TestRequest::get()
+            .uri("/lime/all")
+            .insert_header(("Accept", "application/x-ndjson"))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let rows: Vec<Value> = body.split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).expect("Failed to parse NDJSON row"))
+            .collect();
+        assert!(rows.len() >= 2);
+
+        let req = test::TestRequest::get()
+            .uri("/lime/all?format=rlp")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/x-rlp");
+        let body = test::read_body(resp).await;
+        let transactions = decode_transactions_rlp(&body).expect("Failed to decode RLP response");
+        assert!(transactions.iter().any(|tx| tx.transaction_hash == sample_tx_hash));
+
+        let req = test::TestRequest::get()
+            .uri("/lime/all?format=csv&columns=gasPrice")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
     }
 
     #[actix_web::test]
     async fn test_authenticate_success() {
-        let mut app = test::init_service(App::new().service(authenticate)).await;
+        let pool = setup_test_db();
+        let app = test::init_service(App::new().app_data(test_config()).app_data(Data::new(pool)).service(authenticate)).await;
         let req = test::TestRequest::post()
             .uri("/lime/authenticate")
             .set_json(&AuthData {
@@ -127,13 +291,14 @@ mod tests {
                 password: "alice".into(),
             })
         .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), 200);
     }
 
     #[actix_web::test]
     async fn test_authenticate_failure() {
-        let mut app = test::init_service(App::new().service(authenticate)).await;
+        let pool = setup_test_db();
+        let app = test::init_service(App::new().app_data(test_config()).app_data(Data::new(pool)).service(authenticate)).await;
         let req = test::TestRequest::post()
             .uri("/lime/authenticate")
             .set_json(&AuthData {
@@ -141,7 +306,7 @@ mod tests {
                 password: "emo".into(),
             })
         .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), 401);
     }
 
@@ -150,9 +315,12 @@ mod tests {
         let pool = setup_test_db();
 
         // Set up test server
-        let mut app = test::init_service(
-            App::new()
+        let app = test::init_service(
+            App::new().app_data(test_config())
+            .app_data(test_provider())
+            .app_data(test_prices())
             .app_data(Data::new(pool.clone()))
+            .app_data(test_cache())
             .service(lime_eth_rlphex)
             .service(lime_my)
             .service(authenticate))
@@ -166,7 +334,7 @@ mod tests {
                 password: "alice".into(),
             })
         .to_request();
-        let resp: Value = test::call_and_read_body_json(&mut app, req).await;
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
         let auth_token = resp["token"].as_str().expect("Failed to get token");
 
         // Get some transaction searches for `alice`
@@ -178,7 +346,7 @@ mod tests {
             .insert_header(("AUTH_TOKEN", auth_token))
             .insert_header(ContentType::json())
             .to_request();
-        test::call_service(&mut app, req).await;
+        test::call_service(&app, req).await;
 
         // Get transaction searches for `alice`
         let req = test::TestRequest::get()
@@ -187,7 +355,7 @@ mod tests {
             .insert_header(ContentType::json())
             .to_request();
 
-        let resp: Value = test::call_and_read_body_json(&mut app, req).await;
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
 
         // Check the response to make sure correct list of transactions were returned
         let transactions: Vec<Transaction> = serde_json::from_value(resp["transactions"].clone()).expect("Failed to parse transactions");
@@ -196,4 +364,892 @@ mod tests {
         let transaction = transactions.first().unwrap();
         assert_eq!(transaction.transaction_hash, tx_hash);
     }
+
+    #[actix_web::test]
+    async fn test_register_abi() {
+        let pool = setup_test_db();
+
+        let app = test::init_service(
+            App::new().app_data(test_config())
+            .app_data(Data::new(pool.clone()))
+            .service(register_abi)
+            .service(unregister_abi)
+            .service(authenticate))
+            .await;
+
+        let mut tokens = Vec::new();
+        for username in ["alice", "bob"] {
+            let req = test::TestRequest::post()
+                .uri("/lime/authenticate")
+                .set_json(&AuthData {
+                    username: username.into(),
+                    password: username.into(),
+                })
+            .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            tokens.push(resp["token"].as_str().expect("Failed to get token").to_string());
+        }
+        let (admin_token, user_token) = (&tokens[0], &tokens[1]);
+
+        let contract = "0x4444444444444444444444444444444444444444";
+        let abi = serde_json::json!([{
+            "type": "function",
+            "name": "transfer",
+            "inputs": [{ "name": "recipient", "type": "address" }, { "name": "wad", "type": "uint256" }],
+            "outputs": [{ "name": "", "type": "bool" }],
+            "stateMutability": "nonpayable"
+        }]);
+
+        // Only admins may register ABIs
+        let req = test::TestRequest::post()
+            .uri(&format!("/lime/admin/abi/{}", contract))
+            .set_json(&abi)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/lime/admin/abi/{}", contract))
+            .insert_header(("AUTH_TOKEN", user_token.as_str()))
+            .set_json(&abi)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/lime/admin/abi/{}", contract))
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .set_json(serde_json::json!({ "not": "an abi" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/lime/admin/abi/{}", contract))
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .set_json(&abi)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let abis = crate::abi::get_contract_abis(&pool, &[contract.to_string()]).await.unwrap();
+        assert!(abis.contains_key(contract), "ABI wasn't stored");
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/lime/admin/abi/{}", contract))
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 204);
+    }
+
+    #[actix_web::test]
+    async fn test_lime_events() {
+        let pool = setup_test_db();
+
+        let app = test::init_service(App::new().app_data(test_config()).app_data(Data::new(pool.clone())).service(lime_events)).await;
+
+        // Seed a cached transaction with an ERC-20 `Transfer` log
+        let tx_hash = "0x5555555555555555555555555555555555555555555555555555555555555555";
+        let token = "0x6666666666666666666666666666666666666666";
+        let transaction = Transaction {
+            to: Some(token.to_string()),
+            logs_count: 1,
+            ..test_transaction(tx_hash, "0x7777777777777777777777777777777777777777777777777777777777777777", 100)
+        };
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+        let log = TransactionLog {
+            transaction_hash: tx_hash.to_string(),
+            log_index: 0,
+            block_number: 100,
+            address: token.to_string(),
+            topic0: Some("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef".to_string()),
+            topic1: Some("0x0000000000000000000000001111111111111111111111111111111111111111".to_string()),
+            topic2: Some("0x0000000000000000000000002222222222222222222222222222222222222222".to_string()),
+            topic3: None,
+            data: "0x00000000000000000000000000000000000000000000000000000000000003e8".to_string(),
+        };
+        let second_log = TransactionLog { log_index: 1, ..log.clone() };
+        store_transaction_logs_in_db(&pool, &[log, second_log]).await.unwrap();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/events?address={}&event=Transfer&fromBlock=50&toBlock=150", token))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let events = resp["events"].as_array().expect("Failed to parse events");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "Transfer");
+        assert_eq!(events[0]["transactionHash"], tx_hash);
+        assert_eq!(events[0]["arguments"][2]["value"], "1000");
+        assert!(resp["nextCursor"].is_null());
+
+        // One log per page
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/events?address={}&limit=1", token))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["events"].as_array().unwrap().len(), 1);
+        assert_eq!(resp["events"][0]["logIndex"], 0);
+        let cursor = resp["nextCursor"].as_str().expect("Missing nextCursor").to_string();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/events?address={}&limit=1&cursor={}", token, cursor))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["events"].as_array().unwrap().len(), 1);
+        assert_eq!(resp["events"][0]["logIndex"], 1);
+        assert!(resp["nextCursor"].is_null());
+
+        for query in ["limit=0", "cursor=100"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/lime/events?address={}&{}", token, query))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400);
+        }
+
+        // Outside of the block range
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/events?address={}&event=Transfer&fromBlock=101", token))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert!(resp["events"].as_array().unwrap().is_empty());
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/lime/events?address={}&event=Unknown", token))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_lime_address_tokens() {
+        let pool = setup_test_db();
+
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(Data::new(pool.clone())).service(lime_address_tokens)).await;
+
+        let tx_hash = "0x8888888888888888888888888888888888888888888888888888888888888888";
+        let token = "0x9999999999999999999999999999999999999999";
+        let owner = "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        let other = "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+        // Drop the second token's transfer left over from a previous run
+        diesel::delete(crate::schema::token_transfers::table.filter(crate::schema::token_transfers::transaction_hash.eq(tx_hash)))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        let transaction = Transaction {
+            from: owner.to_string(),
+            to: Some(token.to_string()),
+            logs_count: 2,
+            ..test_transaction(tx_hash, "0x7777777777777777777777777777777777777777777777777777777777777777", 200)
+        };
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+        let transfer = |log_index: i64, from: &str, to: &str, value: &str| TokenTransfer {
+            transaction_hash: tx_hash.to_string(),
+            log_index,
+            block_number: 200,
+            token: token.to_string(),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            value: value.to_string(),
+        };
+        store_token_transfers_in_db(&pool, &[transfer(0, other, owner, "5000"), transfer(1, owner, other, "2000")]).await.unwrap();
+
+        // Cached metadata, so the node isn't asked for it
+        let metadata = TokenMetadata {
+            token: token.to_string(),
+            name: Some("Test Token".to_string()),
+            symbol: Some("TST".to_string()),
+            decimals: Some(6),
+        };
+        diesel::insert_into(crate::schema::token_metadata::table)
+            .values(&metadata)
+            .on_conflict_do_nothing()
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+
+        // Checksummed input is normalized to the stored lowercase form
+        let req = test::TestRequest::get()
+            .uri("/lime/address/0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA/tokens")
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["address"], owner);
+        let tokens = resp["tokens"].as_array().expect("Failed to parse tokens");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0]["symbol"], "TST");
+        assert_eq!(tokens[0]["decimals"], 6);
+        assert_eq!(tokens[0]["received"], "5000");
+        assert_eq!(tokens[0]["sent"], "2000");
+        assert_eq!(tokens[0]["net"], "3000");
+        assert_eq!(tokens[0]["transfers"].as_array().unwrap().len(), 2);
+        assert!(resp["nextCursor"].is_null());
+
+        // A second token, one per page
+        let second_token = "0x9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a9a";
+        let second_metadata = TokenMetadata { token: second_token.to_string(), ..metadata.clone() };
+        diesel::insert_into(crate::schema::token_metadata::table)
+            .values(&second_metadata)
+            .on_conflict_do_nothing()
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        store_token_transfers_in_db(&pool, &[TokenTransfer { token: second_token.to_string(), ..transfer(2, other, owner, "1") }]).await.unwrap();
+
+        let req = test::TestRequest::get().uri(&format!("/lime/address/{}/tokens?limit=1", owner)).to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["tokens"].as_array().unwrap().len(), 1);
+        assert_eq!(resp["tokens"][0]["token"], token);
+        assert_eq!(resp["nextCursor"], token);
+
+        let req = test::TestRequest::get().uri(&format!("/lime/address/{}/tokens?limit=1&after={}", owner, token)).to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["tokens"].as_array().unwrap().len(), 1);
+        assert_eq!(resp["tokens"][0]["token"], second_token);
+        assert_eq!(resp["tokens"][0]["received"], "1");
+        assert!(resp["nextCursor"].is_null());
+
+        for uri in ["/lime/address/not-an-address/tokens", &format!("/lime/address/{}/tokens?limit=0", owner), &format!("/lime/address/{}/tokens?after=0x12", owner)] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_token_metadata_not_stored_when_node_unreachable() {
+        use crate::tokens::get_token_metadata;
+
+        let pool = setup_test_db();
+        let token = "0x9898989898989898989898989898989898989898";
+        let mut conn = pool.get().unwrap();
+        diesel::delete(crate::schema::token_metadata::table.filter(crate::schema::token_metadata::token.eq(token))).execute(&mut conn).unwrap();
+
+        // Nothing listens there
+        let mut config = test_config().get_ref().clone();
+        config.eth.node_url = "http://127.0.0.1:1".to_string();
+        let provider = config.eth_provider().unwrap();
+
+        let metadata = get_token_metadata(&pool, &provider, &[token.to_string()]).await.unwrap();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].symbol, None);
+
+        // Not remembered, so the next request asks the node again
+        let stored: i64 = crate::schema::token_metadata::table
+            .filter(crate::schema::token_metadata::token.eq(token))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_lime_block() {
+        let pool = setup_test_db();
+
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(test_prices()).app_data(Data::new(pool.clone())).service(lime_block)).await;
+
+        // Left behind by the reorg below on a previous run
+        let mut conn = pool.get().unwrap();
+        diesel::delete(crate::schema::blocks::table.filter(crate::schema::blocks::number.eq(300))).execute(&mut conn).unwrap();
+
+        let block_hash = "0xcececececececececececececececececececececececececececececececece";
+        let block = Block {
+            hash: block_hash.to_string(),
+            number: 300,
+            parent_hash: "0xcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcfcf".to_string(),
+            timestamp: 1719726496,
+            miner: "0x4444444444444444444444444444444444444444".to_string(),
+            gas_used: 21000,
+            gas_limit: 30000000,
+            base_fee_per_gas: Some("7".to_string()),
+            transaction_count: 1,
+        };
+        store_block_in_db(&pool, &block).await.unwrap();
+        let tx_hash = "0xcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+        let transaction = test_transaction(tx_hash, block_hash, 300);
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+
+        for block_ref in ["300", "0x12c", block_hash] {
+            let req = test::TestRequest::get().uri(&format!("/lime/block/{}", block_ref)).to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["block"]["hash"], block_hash);
+            assert_eq!(resp["block"]["timestamp"], 1719726496);
+            let transactions = resp["transactions"].as_array().expect("Failed to parse transactions");
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0]["transactionHash"], tx_hash);
+            assert_eq!(transactions[0]["blockTimestamp"], 1719726496);
+        }
+
+        let req = test::TestRequest::get().uri("/lime/block/latest").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        // A reorged block at the same height leaves the choice to the node, which is down here
+        let reorged = Block { hash: "0xcacacacacacacacacacacacacacacacacacacacacacacacacacacacacacacaca".to_string(), ..block };
+        store_block_in_db(&pool, &reorged).await.unwrap();
+        let mut config = Config::default();
+        config.eth.node_url = "http://127.0.0.1:1".to_string();
+        let app = test::init_service(App::new()
+            .app_data(test_config())
+            .app_data(Data::new(config.eth_provider().unwrap()))
+            .app_data(test_prices())
+            .app_data(Data::new(pool.clone()))
+            .service(lime_block)).await;
+        let req = test::TestRequest::get().uri("/lime/block/300").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 502);
+        let req = test::TestRequest::get().uri(&format!("/lime/block/{}", block_hash)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_load_prices() {
+        let pool = setup_test_db();
+
+        let app = test::init_service(
+            App::new().app_data(test_config())
+            .app_data(test_provider())
+            .app_data(test_prices())
+            .app_data(Data::new(pool.clone()))
+            .service(load_prices)
+            .service(lime_block)
+            .service(authenticate))
+            .await;
+
+        let mut tokens = Vec::new();
+        for username in ["alice", "bob"] {
+            let req = test::TestRequest::post()
+                .uri("/lime/authenticate")
+                .set_json(&AuthData {
+                    username: username.into(),
+                    password: username.into(),
+                })
+            .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            tokens.push(resp["token"].as_str().expect("Failed to get token").to_string());
+        }
+        let (admin_token, user_token) = (&tokens[0], &tokens[1]);
+
+        // A block on 2020-01-01 with a 2 ether transfer
+        let block_hash = "0xc1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1";
+        let block = Block {
+            hash: block_hash.to_string(),
+            number: 400,
+            parent_hash: "0xc2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2c2".to_string(),
+            timestamp: 1577880000,
+            miner: "0x4444444444444444444444444444444444444444".to_string(),
+            gas_used: 21000,
+            gas_limit: 30000000,
+            base_fee_per_gas: None,
+            transaction_count: 1,
+        };
+        store_block_in_db(&pool, &block).await.unwrap();
+        let transaction = Transaction {
+            to: Some("0x2222222222222222222222222222222222222222".to_string()),
+            value: "2000000000000000000".to_string(),
+            ..test_transaction("0xc3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3c3", block_hash, 400)
+        };
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+
+        let csv = "date,currency,price\n2020-01-01,USD,130.25\n";
+        let req = test::TestRequest::post()
+            .uri("/lime/admin/prices")
+            .insert_header(("AUTH_TOKEN", user_token.as_str()))
+            .set_payload(csv)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let req = test::TestRequest::post()
+            .uri("/lime/admin/prices")
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .set_payload("2020-01-01,USD")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        let req = test::TestRequest::post()
+            .uri("/lime/admin/prices")
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .set_payload(csv)
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["stored"], 1);
+
+        let req = test::TestRequest::get().uri(&format!("/lime/block/{}", block_hash)).to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        let fiat = &resp["transactions"][0]["fiat"]["USD"];
+        assert_eq!(fiat["day"], "2020-01-01");
+        assert_eq!(fiat["price"], "130.25");
+        assert_eq!(fiat["value"], "260.50");
+    }
+
+    #[actix_web::test]
+    async fn test_load_daily_prices() {
+        use crate::prices::{CsvPriceSource, PriceSource, load_daily_prices};
+        use crate::schema::eth_prices;
+
+        let pool = setup_test_db();
+        let mut conn = pool.get().unwrap();
+        diesel::delete(eth_prices::table.filter(eth_prices::day.eq_any(["2019-03-01", "2019-03-02"]))).execute(&mut conn).unwrap();
+        diesel::insert_into(eth_prices::table)
+            .values((eth_prices::currency.eq("USD"), eth_prices::day.eq("2019-03-01"), eth_prices::price.eq("135")))
+            .execute(&mut conn)
+            .unwrap();
+
+        // The stored price wins over the fallback, which only fills the gaps
+        let fallback = CsvPriceSource::parse("2019-03-01,USD,999\n2019-03-02,USD,136\n").unwrap();
+        let currencies = vec!["USD".to_string(), "EUR".to_string()];
+        let days = vec!["2019-03-01".to_string(), "2019-03-02".to_string()];
+        let prices = load_daily_prices(&pool, Some(&fallback), &currencies, &days).await.unwrap();
+        assert_eq!(prices.daily_price("USD", "2019-03-01").as_deref(), Some("135"));
+        assert_eq!(prices.daily_price("USD", "2019-03-02").as_deref(), Some("136"));
+        assert_eq!(prices.daily_price("EUR", "2019-03-01"), None);
+
+        // What the fallback answered is stored
+        let prices = load_daily_prices(&pool, None, &currencies, &days).await.unwrap();
+        assert_eq!(prices.daily_price("USD", "2019-03-02").as_deref(), Some("136"));
+    }
+
+    #[actix_web::test]
+    async fn test_store_prices_large_upload() {
+        use crate::prices::{EthPrice, store_prices};
+        use crate::schema::eth_prices;
+
+        let pool = setup_test_db();
+        // More rows than a single insert statement can bind
+        let prices: Vec<EthPrice> = (0..22_000)
+            .map(|index| EthPrice { currency: format!("T{:05}", index), day: "2018-01-01".to_string(), price: "1".to_string() })
+            .collect();
+        assert_eq!(store_prices(&pool, &prices).await.unwrap(), 22_000);
+
+        let mut conn = pool.get().unwrap();
+        diesel::delete(eth_prices::table.filter(eth_prices::day.eq("2018-01-01"))).execute(&mut conn).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_lime_export_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let pool = setup_test_db();
+
+        let app = test::init_service(
+            App::new().app_data(test_config())
+            .app_data(Data::new(pool.clone()))
+            .service(lime_export_parquet)
+            .service(authenticate))
+            .await;
+
+        let mut tokens = Vec::new();
+        for username in ["alice", "bob"] {
+            let req = test::TestRequest::post()
+                .uri("/lime/authenticate")
+                .set_json(&AuthData {
+                    username: username.into(),
+                    password: username.into(),
+                })
+            .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            tokens.push(resp["token"].as_str().expect("Failed to get token").to_string());
+        }
+        let (admin_token, user_token) = (&tokens[0], &tokens[1]);
+
+        let transaction = Transaction {
+            to: Some("0x2222222222222222222222222222222222222222".to_string()),
+            value: "1".to_string(),
+            ..test_transaction("0xe5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5e5", "0xe6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6", 500)
+        };
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/lime/export/parquet?fromBlock=500&toBlock=500")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::get()
+            .uri("/lime/export/parquet?fromBlock=500&toBlock=500")
+            .insert_header(("AUTH_TOKEN", user_token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let req = test::TestRequest::get()
+            .uri("/lime/export/parquet?fromBlock=500&toBlock=500")
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/vnd.apache.parquet");
+        let body = test::read_body(resp).await;
+        let reader = SerializedFileReader::new(body).expect("Failed to read Parquet file");
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 1);
+
+        let req = test::TestRequest::get()
+            .uri("/lime/export/parquet?address=0x12")
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_import_transactions() {
+        use crate::import::{import_transactions, ImportFormat};
+        use crate::schema::transactions::dsl::{transactions, transaction_hash};
+
+        let pool = setup_test_db();
+        let hash = "0xe7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7";
+        let mut conn = pool.get().expect("Failed to get connection from pool.");
+        diesel::delete(transactions.filter(transaction_hash.eq(hash))).execute(&mut conn).unwrap();
+
+        let csv = format!("transactionHash,transactionStatus,blockHash,blockNumber,from,to,contractAddress,logsCount,input,value\n\
+            {},true,0xE8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8E8,600,0x1111111111111111111111111111111111111111,,,0,0x,1\n\
+            {},true,0x12,600,0x1111111111111111111111111111111111111111,,,0,0x,1\n\
+            {},true,0xe8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8,600,0x1111111111111111111111111111111111111111,,,0,0x,1\n",
+            hash.to_uppercase().replace("0X", "0x"), hash, hash);
+
+        let report = import_transactions(&pool, csv.as_bytes(), ImportFormat::Csv, 1).unwrap();
+        assert_eq!((report.inserted, report.skipped, report.invalid), (1, 1, 1));
+        assert_eq!(report.errors[0].0, 2);
+
+        let stored: Transaction = transactions.filter(transaction_hash.eq(hash)).first(&mut conn).unwrap();
+        assert_eq!(stored.block_hash, "0xe8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8e8");
+        assert_eq!(stored.to, None);
+    }
+
+    #[actix_web::test]
+    async fn test_import_transactions_large_batch() {
+        use crate::import::{import_transactions, ImportFormat};
+        use crate::schema::transactions::dsl::{transactions, block_hash};
+        use ethers::types::H256;
+
+        let pool = setup_test_db();
+        let block = "0xe9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9e9";
+        let mut conn = pool.get().expect("Failed to get connection from pool.");
+        diesel::delete(transactions.filter(block_hash.eq(block))).execute(&mut conn).unwrap();
+
+        // More rows in one batch than a single insert statement can bind
+        let ndjson: String = (0..7000u64)
+            .map(|index| {
+                let transaction = test_transaction(&format!("{:?}", H256::from_low_u64_be(0xe9e9_0000_0000 + index)), block, 700);
+                format!("{}\n", serde_json::to_string(&transaction).unwrap())
+            })
+            .collect();
+        let report = import_transactions(&pool, ndjson.as_bytes(), ImportFormat::Ndjson, 10_000).unwrap();
+        assert_eq!((report.inserted, report.invalid), (7000, 0));
+
+        // An unreachable database fails the import instead of panicking
+        let unreachable = r2d2::Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::<PgConnection>::new("postgresql://nobody@127.0.0.1:1/nothing"));
+        let error = import_transactions(&unreachable, ndjson.as_bytes(), ImportFormat::Ndjson, 10).unwrap_err();
+        assert!(error.starts_with("Failed to insert transactions"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn test_health_endpoints() {
+        use crate::health::{healthz, readyz};
+
+        let pool = setup_test_db();
+        let app = test::init_service(App::new().app_data(test_config()).app_data(test_provider()).app_data(Data::new(pool)).service(healthz).service(readyz)).await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["status"], "ok");
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["checks"]["database"]["status"], "ok");
+        assert_eq!(body["checks"]["migrations"]["status"], "ok");
+        assert!(body["checks"]["ethNode"]["latencyMs"].is_number());
+        assert_eq!(status == 200, body["status"] == "ok");
+    }
+
+    #[actix_web::test]
+    async fn test_user_admin() {
+        use crate::users::{add_user, list_users, remove_user};
+
+        let pool = setup_test_db();
+        remove_user(&pool, "erin").await.unwrap();
+
+        assert!(add_user(&pool, "erin", "s3cret").await.unwrap());
+        assert!(!add_user(&pool, "erin", "other").await.unwrap());
+        assert!(list_users(&pool).await.unwrap().contains(&"erin".to_string()));
+
+        let app = test::init_service(App::new().app_data(test_config()).app_data(Data::new(pool.clone())).service(authenticate)).await;
+        for (password, status) in [("s3cret", 200), ("erin", 401)] {
+            let req = test::TestRequest::post()
+                .uri("/lime/authenticate")
+                .set_json(&AuthData { username: "erin".into(), password: password.into() })
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+
+        assert!(remove_user(&pool, "erin").await.unwrap());
+        assert!(!remove_user(&pool, "erin").await.unwrap());
+        assert!(!list_users(&pool).await.unwrap().contains(&"erin".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_cache_purge() {
+        use crate::cache::{cache_stats, purge_transactions};
+        use crate::users::store_user_search;
+
+        let pool = setup_test_db();
+        let hash = "0xf1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1f1";
+        let transaction = test_transaction(hash, "0xf2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2", 700);
+        purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap();
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+        store_user_search(Data::new(pool.clone()), "bob", hash).await.unwrap();
+        assert!(cache_stats(&pool).await.unwrap().transactions >= 1);
+
+        assert_eq!(purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap(), 1);
+        assert_eq!(purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap(), 0);
+
+        // Bob's search outlives the cached transaction
+        let mut conn = pool.get().unwrap();
+        let searches: i64 = crate::schema::users_searches::table
+            .filter(crate::schema::users_searches::username.eq("bob"))
+            .filter(crate::schema::users_searches::transaction_hash.eq(hash))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(searches, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_cache() {
+        use crate::cache::{lime_cache_purge, lime_cache_stats, purge_transactions};
+
+        let pool = setup_test_db();
+        let cache = test_cache();
+        let app = test::init_service(
+            App::new().app_data(test_config())
+            .app_data(test_provider())
+            .app_data(test_prices())
+            .app_data(Data::new(pool.clone()))
+            .app_data(cache.clone())
+            .service(lime_eth_transactions_hashes)
+            .service(lime_cache_stats)
+            .service(lime_cache_purge)
+            .service(authenticate))
+            .await;
+
+        let mut tokens = Vec::new();
+        for username in ["alice", "bob"] {
+            let req = test::TestRequest::post()
+                .uri("/lime/authenticate")
+                .set_json(&AuthData { username: username.into(), password: username.into() })
+                .to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            tokens.push(resp["token"].as_str().expect("Failed to get token").to_string());
+        }
+        let (admin_token, user_token) = (&tokens[0], &tokens[1]);
+
+        let hash = "0xf5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5f5";
+        let transaction = test_transaction(hash, "0xf6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6", 702);
+        purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap();
+        store_transaction_in_db(Data::new(pool.clone()), &transaction).await.unwrap();
+
+        // The first lookup reads the database and fills the memory cache, the second one is answered from memory
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri(&format!("/lime/eth?transactionHashes={}", hash)).to_request();
+            let resp: Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["transactions"][0]["transactionHash"], hash);
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 1));
+
+        let req = test::TestRequest::get().uri("/lime/admin/cache").insert_header(("AUTH_TOKEN", user_token.as_str())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+        let req = test::TestRequest::get().uri("/lime/admin/cache").insert_header(("AUTH_TOKEN", admin_token.as_str())).to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["memory"]["hitRate"], 0.5);
+        assert!(resp["database"]["transactions"].as_i64().unwrap() >= 1);
+
+        let req = test::TestRequest::delete().uri("/lime/admin/cache").insert_header(("AUTH_TOKEN", admin_token.as_str())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+        let req = test::TestRequest::delete()
+            .uri(&format!("/lime/admin/cache?transactionHashes={}", hash.to_uppercase().replace("0X", "0x")))
+            .insert_header(("AUTH_TOKEN", admin_token.as_str()))
+            .to_request();
+        let resp: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["purged"], 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_transactions() {
+        use crate::cache::purge_transactions;
+        use crate::routes::lookup_transactions;
+        use crate::schema::unknown_transactions;
+        use ethers::types::H256;
+        use std::str::FromStr;
+
+        let pool = Data::new(setup_test_db());
+        let mut config = test_config().get_ref().clone();
+        config.cache.negative_table = true;
+        // No chain has this one
+        let tx_hash = "0x00000000000000000000000000000000000000000000000000000000000000a1";
+        let hashes = [H256::from_str(tx_hash).unwrap()];
+        purge_transactions(&pool, Some(&[tx_hash.to_string()])).await.unwrap();
+
+        let provider = test_provider();
+        let cache = test_cache();
+        assert!(lookup_transactions(&pool, &config, &provider, &cache, &hashes, false, None).await.is_empty());
+        assert!(lookup_transactions(&pool, &config, &provider, &cache, &hashes, false, None).await.is_empty());
+        assert_eq!(cache.stats().unknown_hits, 1);
+
+        let checked_at = || {
+            let mut conn = pool.get().unwrap();
+            unknown_transactions::table
+                .select(unknown_transactions::checked_at)
+                .filter(unknown_transactions::transaction_hash.eq(tx_hash))
+                .first::<i64>(&mut conn)
+                .unwrap()
+        };
+        let first_check = checked_at();
+
+        // Another instance (or a restart) finds it in the table
+        let other_cache = test_cache();
+        assert!(lookup_transactions(&pool, &config, &provider, &other_cache, &hashes, false, None).await.is_empty());
+        assert_eq!(other_cache.stats().unknown_entries, 1);
+        assert_eq!(other_cache.stats().unknown_hits, 0);
+
+        // Refreshing asks the node again, which still doesn't know it. Backdate the
+        // check first so the new one is strictly newer even within the same second.
+        let stale = first_check - 100;
+        diesel::update(unknown_transactions::table.filter(unknown_transactions::transaction_hash.eq(tx_hash)))
+            .set(unknown_transactions::checked_at.eq(stale))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        assert!(lookup_transactions(&pool, &config, &provider, &other_cache, &hashes, true, None).await.is_empty());
+        assert!(checked_at() > stale);
+
+        assert_eq!(purge_transactions(&pool, Some(&[tx_hash.to_string()])).await.unwrap(), 0);
+        let mut conn = pool.get().unwrap();
+        let remembered: i64 = unknown_transactions::table.filter(unknown_transactions::transaction_hash.eq(tx_hash)).count().get_result(&mut conn).unwrap();
+        assert_eq!(remembered, 0);
+    }
+
+    /// Concurrent lookups of the same transaction by the same user used to race
+    /// between the existence check and the insert, failing on the primary key
+    #[actix_web::test]
+    async fn test_concurrent_stores() {
+        use crate::cache::purge_transactions;
+        use crate::db::with_conn;
+        use crate::transaction::insert_transactions;
+        use crate::users::insert_user_searches;
+        use crate::schema::users_searches;
+        use futures_util::future::join_all;
+
+        let pool = setup_test_db();
+        let hash = "0xf3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3f3";
+        let transaction = test_transaction(hash, "0xf4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4f4", 701);
+        purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap();
+
+        let results = join_all((0..8).map(|_| {
+            let transaction = transaction.clone();
+            with_conn(&pool, move |conn| conn.transaction(|conn| {
+                let inserted = insert_transactions(conn, &[transaction])?;
+                insert_user_searches(conn, "carol", &[hash.to_string()])?;
+                Ok(inserted)
+            }))
+        })).await;
+        let inserted: Vec<usize> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(inserted.iter().sum::<usize>(), 1);
+
+        let mut conn = pool.get().unwrap();
+        let searches: i64 = users_searches::table
+            .filter(users_searches::username.eq("carol"))
+            .filter(users_searches::transaction_hash.eq(hash))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(searches, 1);
+        purge_transactions(&pool, Some(&[hash.to_string()])).await.unwrap();
+    }
+
+    /// 8 queries on the single threaded test runtime, each holding its connection
+    /// until all 8 are running: only possible if `with_conn` runs them side by side
+    #[actix_web::test]
+    async fn test_queries_run_concurrently() {
+        use crate::db::with_conn;
+        use futures_util::future::join_all;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Instant;
+
+        const REQUESTS: usize = 8;
+        let pool = setup_test_db();
+        let running = Arc::new(AtomicUsize::new(0));
+
+        let results = join_all((0..REQUESTS).map(|_| {
+            let running = running.clone();
+            with_conn(&pool, move |conn| {
+                diesel::sql_query("SELECT 1").execute(conn)?;
+                running.fetch_add(1, Ordering::SeqCst);
+                // Run one after the other, the first one would wait for the others in vain
+                let deadline = Instant::now() + Duration::from_secs(10);
+                while running.load(Ordering::SeqCst) < REQUESTS && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Ok(running.load(Ordering::SeqCst))
+            })
+        })).await;
+
+        for result in results {
+            assert_eq!(result.unwrap(), REQUESTS);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_lime_ws_answers_pings_during_lookups() {
+        use crate::ws::lime_ws;
+        use actix_web::body::MessageBody;
+        use futures_util::future::poll_fn;
+
+        // A node that accepts connections and never answers, so lookups hang
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = Config::clone(&test_config());
+        config.eth.node_url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let held: Vec<_> = listener.incoming().collect();
+            drop(held);
+        });
+        let provider = Data::new(config.eth_provider().unwrap());
+
+        let app = test::init_service(App::new().app_data(Data::new(config)).app_data(provider).app_data(Data::new(setup_test_db())).app_data(test_cache()).app_data(test_prices()).service(lime_ws)).await;
+
+        // Client frames are masked, an all-zero mask leaves the payload as is
+        let frame = |opcode: u8, payload: &[u8]| [&[0x80 | opcode, 0x80 | payload.len() as u8, 0, 0, 0, 0][..], payload].concat();
+        let payload = [
+            frame(0x1, b"not json"),
+            frame(0x1, br#"{"transactionHashes": ["0x0101010101010101010101010101010101010101010101010101010101010101"]}"#),
+            frame(0x9, b"hi"),
+        ].concat();
+        let req = test::TestRequest::get()
+            .uri("/lime/ws")
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .set_payload(payload)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 101);
+
+        // Server frames are unmasked, (opcode, payload) of each until the close frame
+        let mut body = std::pin::pin!(resp.into_body());
+        let mut buffer = Vec::new();
+        let mut frames = Vec::new();
+        while frames.last().map(|(opcode, _): &(u8, Vec<u8>)| *opcode) != Some(0x8) {
+            let chunk = actix_web::rt::time::timeout(Duration::from_secs(5), poll_fn(|cx| body.as_mut().poll_next(cx)))
+                .await
+                .expect("Timed out waiting for a frame")
+                .expect("Socket closed without a close frame")
+                .unwrap();
+            buffer.extend_from_slice(&chunk);
+            while buffer.len() >= 2 && buffer.len() >= 2 + (buffer[1] & 0x7f) as usize {
+                let len = (buffer[1] & 0x7f) as usize;
+                frames.push((buffer[0] & 0x0f, buffer[2..2 + len].to_vec()));
+                buffer.drain(..2 + len);
+            }
+        }
+
+        assert_eq!(frames.len(), 3);
+        let error: Value = serde_json::from_slice(&frames[0].1).unwrap();
+        assert_eq!(frames[0].0, 0x1);
+        assert!(error["error"].is_string());
+        assert!(error.get("transactionHash").is_none());
+        // Answered while the lookup is still waiting on the node
+        assert_eq!(frames[1], (0xA, b"hi".to_vec()));
+        assert_eq!(frames[2].0, 0x8);
+    }
 }
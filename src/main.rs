@@ -3,17 +3,40 @@ extern crate diesel;
 extern crate actix_web;
 
 use actix_web::{App, HttpServer, web::Data};
+use actix_web::middleware::from_fn;
 use dotenv::dotenv;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::pg::PgConnection;
 use std::env;
 
+use cli::Command;
+use config::Config;
+
 mod routes;
+mod response;
 mod schema;
 mod transaction;
+mod raw_transaction;
 mod setup;
 mod auth;
+mod abi;
+mod analytics;
+mod blocks;
+mod cache;
+mod cli;
+mod config;
+mod db;
+mod events;
+mod export;
+mod health;
+mod import;
+mod metrics;
+mod prices;
+mod telemetry;
+mod tokens;
+mod traces;
 mod users;
+mod ws;
 mod integration_tests;
 
 pub type DBPool = Pool<ConnectionManager<PgConnection>>;
@@ -22,28 +45,125 @@ pub type DBPool = Pool<ConnectionManager<PgConnection>>;
 async fn main() -> std::io::Result<()> {
     // Load `.env` file with environment variables if present
     dotenv().ok();
+    telemetry::init_tracing();
+    telemetry::trace_queries();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let api_port = env::var("API_PORT").unwrap_or_else(|_| "8080".to_string());
+    let manager = ConnectionManager::<PgConnection>::new(&config.database.url);
+    let pool = Pool::builder()
+        .max_size(config.database.pool_size)
+        .min_idle(config.database.min_idle)
+        .connection_timeout(config.connection_timeout())
+        .event_handler(Box::new(metrics::PoolEventHandler))
+        // Connections are opened on demand, so a missing Postgres is retried below instead of failing here
+        .build_unchecked(manager);
 
-    let database_url = env::var("DB_CONNECTION_URL").expect("DB_CONNECTION_URL must be set");
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool = Pool::builder().build(manager).expect("Failed to create pool.");
+    let database = setup::wait_for_database(&pool, config.database.startup_retries, config.retry_backoff(), command.needs_migrations()).await;
+    // Commands always need the database, the server only when degraded mode is off
+    let degraded = command == Command::Serve && config.database.degraded_mode;
+    if let Err(e) = &database {
+        if !degraded {
+            eprintln!("Database unavailable: {}", e);
+            std::process::exit(1);
+        }
+        tracing::warn!(error = %e, "Database unavailable, starting in degraded mode");
+    }
 
-    let mut conn = pool.get().expect("Failed to get connection from pool.");
-    setup::run_migrations(&mut conn);
+    if command != Command::Serve {
+        if let Err(e) = cli::run(command, &pool, &config).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
+    let bind_address = config.bind_address();
+    tracing::info!(address = %bind_address.0, port = bind_address.1, "Starting server");
+    if config.eth.expected_chain_id.is_none() {
+        tracing::warn!("eth.expected_chain_id (EXPECTED_CHAIN_ID) is not set, /readyz accepts a node on any chain");
+    }
+    let database_status = Data::new(setup::DatabaseStatus::new(database.is_ok()));
+    if degraded {
+        actix_web::rt::spawn(setup::monitor_database(pool.clone(), database_status.get_ref().clone(), config.retry_backoff()));
+    }
+    // Shared by all workers, like the database it caches
+    let transaction_cache = Data::new(cache::TransactionCache::from_config(&config));
+    // One HTTP client for the node, shared by all workers
+    let eth_provider = match config.eth_provider() {
+        Ok(provider) => Data::new(provider),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Fails here rather than on the first lookup that needs a price
+    let csv_prices = match prices::CsvPriceSource::from_config(&config) {
+        Ok(prices) => Data::new(prices),
+        Err(e) => {
+            eprintln!("Invalid prices.csv: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let config = Data::new(config);
     HttpServer::new(move || {
         App::new()
             // Set up DB pool to be used with web::Data<Pool> extractor
             .app_data(Data::new(pool.clone()))
+            .app_data(config.clone())
+            .app_data(database_status.clone())
+            .app_data(transaction_cache.clone())
+            .app_data(eth_provider.clone())
+            .app_data(csv_prices.clone())
+            // Only routes that can do without the database while it is down in degraded mode
+            .wrap(from_fn(setup::require_database))
+            // Count and time every request for `/metrics`
+            .wrap(from_fn(metrics::track_requests))
+            // Request id span around everything else, echoed in `X-Request-Id`
+            .wrap(from_fn(telemetry::trace_requests))
             // register HTTP requests handlers
             .service(auth::authenticate)
             .service(routes::lime_eth_transactions_hashes)
+            .service(routes::lime_eth_batch)
+            .service(routes::lime_eth_decode)
             .service(routes::lime_eth_rlphex)
+            .service(traces::lime_eth_trace)
             .service(routes::lime_all)
             .service(routes::lime_my)
+            .service(analytics::lime_export_parquet)
+            .service(ws::lime_ws)
+            .service(abi::register_abi)
+            .service(abi::unregister_abi)
+            .service(prices::load_prices)
+            .service(cache::lime_cache_stats)
+            .service(cache::lime_cache_purge)
+            .service(events::lime_events)
+            .service(tokens::lime_address_tokens)
+            .service(blocks::lime_block)
+            .service(metrics::prometheus_metrics)
+            .service(health::healthz)
+            .service(health::readyz)
     })
-    .bind(format!("0.0.0.0:{}", api_port))?
+    .bind(bind_address)?
     .run()
     .await
 }
@@ -0,0 +1,258 @@
+use std::fmt::Debug;
+use std::sync::OnceLock;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use async_trait::async_trait;
+use diesel::r2d2::HandleEvent;
+use diesel::r2d2::event::{CheckoutEvent, TimeoutEvent};
+use ethers::providers::JsonRpcClient;
+use jsonwebtoken::errors::{Error as JwtError, ErrorKind as JwtErrorKind};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, Histogram, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::Instrument;
+
+use crate::DBPool;
+
+/// Everything exposed on `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub cache_lookups: IntCounterVec,
+    pub memory_cache_lookups: IntCounterVec,
+    pub rpc_requests: IntCounterVec,
+    pub rpc_errors: IntCounterVec,
+    pub rpc_request_duration: HistogramVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_wait: Histogram,
+    pub db_pool_timeouts: IntCounter,
+    pub jwt_failures: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        ).unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["method", "route"],
+        ).unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("transaction_cache_lookups_total", "Transaction lookups answered from the cache (hit) or the node (miss)"),
+            &["result"],
+        ).unwrap();
+        let memory_cache_lookups = IntCounterVec::new(
+            Opts::new("transaction_memory_cache_lookups_total", "Transaction lookups answered from memory (hit) or not (miss)"),
+            &["result"],
+        ).unwrap();
+        let rpc_requests = IntCounterVec::new(
+            Opts::new("eth_rpc_requests_total", "JSON-RPC requests sent to the Ethereum node"),
+            &["method"],
+        ).unwrap();
+        let rpc_errors = IntCounterVec::new(
+            Opts::new("eth_rpc_errors_total", "JSON-RPC requests to the Ethereum node that failed"),
+            &["method"],
+        ).unwrap();
+        let rpc_request_duration = HistogramVec::new(
+            HistogramOpts::new("eth_rpc_request_duration_seconds", "Latency of the Ethereum node by JSON-RPC method"),
+            &["method"],
+        ).unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state (idle, in_use, max)"),
+            &["state"],
+        ).unwrap();
+        let db_pool_wait = Histogram::with_opts(
+            HistogramOpts::new("db_pool_wait_seconds", "Time spent waiting for a database connection")
+                .buckets(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0]),
+        ).unwrap();
+        let db_pool_timeouts = IntCounter::new("db_pool_timeouts_total", "Database connection checkouts that timed out").unwrap();
+        let jwt_failures = IntCounterVec::new(
+            Opts::new("jwt_auth_failures_total", "AUTH_TOKEN headers that failed verification"),
+            &["reason"],
+        ).unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+        registry.register(Box::new(memory_cache_lookups.clone())).unwrap();
+        registry.register(Box::new(rpc_requests.clone())).unwrap();
+        registry.register(Box::new(rpc_errors.clone())).unwrap();
+        registry.register(Box::new(rpc_request_duration.clone())).unwrap();
+        registry.register(Box::new(db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(db_pool_wait.clone())).unwrap();
+        registry.register(Box::new(db_pool_timeouts.clone())).unwrap();
+        registry.register(Box::new(jwt_failures.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            cache_lookups,
+            memory_cache_lookups,
+            rpc_requests,
+            rpc_errors,
+            rpc_request_duration,
+            db_pool_connections,
+            db_pool_wait,
+            db_pool_timeouts,
+            jwt_failures,
+        }
+    }
+
+    /// Prometheus text format of every metric
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics are valid text");
+        String::from_utf8(buffer).expect("metrics are valid UTF-8")
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Counts and times every request. Requests are labelled with the route
+/// pattern (e.g. `/lime/eth/{rlphex}`) rather than the path to keep the
+/// number of series bounded.
+pub async fn track_requests(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let timer = std::time::Instant::now();
+
+    let result = next.call(req).await;
+
+    let (route, status) = match &result {
+        Ok(res) => (
+            res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string()),
+            res.status(),
+        ),
+        Err(e) => ("unmatched".to_string(), e.as_response_error().status_code()),
+    };
+    metrics().http_requests.with_label_values(&[&method, &route, status.as_str()]).inc();
+    metrics().http_request_duration.with_label_values(&[&method, &route]).observe(timer.elapsed().as_secs_f64());
+
+    result
+}
+
+pub fn record_cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics().cache_lookups.with_label_values(&[result]).inc();
+}
+
+/// Lookup of a hash the node recently didn't know, answered without asking it again
+pub fn record_unknown_lookup() {
+    metrics().cache_lookups.with_label_values(&["unknown"]).inc();
+}
+
+pub fn record_memory_cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics().memory_cache_lookups.with_label_values(&[result]).inc();
+}
+
+pub fn record_jwt_failure(error: &JwtError) {
+    let reason = match error.kind() {
+        JwtErrorKind::ExpiredSignature => "expired",
+        JwtErrorKind::InvalidSignature => "invalid_signature",
+        _ => "invalid",
+    };
+    metrics().jwt_failures.with_label_values(&[reason]).inc();
+}
+
+/// r2d2 pool events, gives the time each checkout waited for a connection
+#[derive(Debug)]
+pub struct PoolEventHandler;
+
+impl HandleEvent for PoolEventHandler {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        metrics().db_pool_wait.observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        metrics().db_pool_wait.observe(event.timeout().as_secs_f64());
+        metrics().db_pool_timeouts.inc();
+    }
+}
+
+/// JSON-RPC transport that counts, times and traces every request by method,
+/// wrapping the transport the provider would use otherwise
+#[derive(Debug, Clone)]
+pub struct MeteredClient<C>(pub C);
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for MeteredClient<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let timer = metrics().rpc_request_duration.with_label_values(&[method]).start_timer();
+        let result = self.0.request(method, params).instrument(tracing::info_span!("eth_rpc", method)).await;
+        timer.observe_duration();
+
+        metrics().rpc_requests.with_label_values(&[method]).inc();
+        if result.is_err() {
+            metrics().rpc_errors.with_label_values(&[method]).inc();
+        }
+        result
+    }
+}
+
+#[get("/metrics")]
+pub async fn prometheus_metrics(pool: web::Data<DBPool>) -> impl Responder {
+    let state = pool.state();
+    let connections = &metrics().db_pool_connections;
+    connections.with_label_values(&["idle"]).set(state.idle_connections as i64);
+    connections.with_label_values(&["in_use"]).set((state.connections - state.idle_connections) as i64);
+    connections.with_label_values(&["max"]).set(pool.max_size() as i64);
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics().encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use actix_web::middleware::from_fn;
+
+    #[get("/test/metrics/{id}")]
+    async fn route_with_id(path: web::Path<String>) -> impl Responder {
+        HttpResponse::Ok().body(path.into_inner())
+    }
+
+    #[actix_web::test]
+    async fn test_track_requests_by_route_pattern() {
+        let app = test::init_service(App::new().wrap(from_fn(track_requests)).service(route_with_id)).await;
+
+        for id in ["1", "2"] {
+            let req = test::TestRequest::get().uri(&format!("/test/metrics/{}", id)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+        let req = test::TestRequest::get().uri("/test/unknown").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        let requests = &metrics().http_requests;
+        assert_eq!(requests.with_label_values(&["GET", "/test/metrics/{id}", "200"]).get(), 2);
+        assert!(requests.with_label_values(&["GET", "unmatched", "404"]).get() >= 1);
+        assert!(metrics().encode().contains("http_request_duration_seconds_count{method=\"GET\",route=\"/test/metrics/{id}\"} 2"));
+    }
+
+    #[actix_web::test]
+    async fn test_record_jwt_failure() {
+        let before = metrics().jwt_failures.with_label_values(&["expired"]).get();
+        record_jwt_failure(&JwtError::from(JwtErrorKind::ExpiredSignature));
+        assert_eq!(metrics().jwt_failures.with_label_values(&["expired"]).get(), before + 1);
+    }
+}
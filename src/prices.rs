@@ -0,0 +1,302 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use actix_web::{web, post, Responder, HttpResponse, HttpRequest};
+use serde::Serialize;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+use ethers::types::U256;
+use tracing::error;
+
+use crate::DBPool;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::auth::require_admin;
+use crate::config::Config;
+use crate::schema::eth_prices;
+
+/// Daily ETH price in one fiat currency, `day` is an ISO 8601 date (UTC) and
+/// `price` a decimal string such as `3456.78`
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[diesel(table_name = eth_prices)]
+pub struct EthPrice {
+    pub currency: String,
+    pub day: String,
+    pub price: String,
+}
+
+/// Where historical prices come from. Lookups are by day, so any source of
+/// daily closing prices can be plugged in.
+pub trait PriceSource {
+    fn daily_price(&self, currency: &str, day: &str) -> Option<String>;
+}
+
+/// Prices loaded from a `date,currency,price` CSV file
+#[derive(Debug, Default)]
+pub struct CsvPriceSource {
+    prices: HashMap<(String, String), String>,
+}
+
+impl CsvPriceSource {
+    /// Blank lines and a header line are skipped, any other malformed line is an error
+    pub fn parse(csv: &str) -> Result<Self, String> {
+        let mut prices = HashMap::new();
+        for price in parse_price_csv(csv)? {
+            prices.insert((price.currency, price.day), price.price);
+        }
+        Ok(CsvPriceSource { prices })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let csv = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        Self::parse(&csv)
+    }
+
+    /// File set through `prices.csv`, empty without one. Read once at startup,
+    /// so a malformed file stops the server instead of the first lookup.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        match &config.prices.csv {
+            Some(path) => Self::from_file(path),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+impl PriceSource for CsvPriceSource {
+    fn daily_price(&self, currency: &str, day: &str) -> Option<String> {
+        self.prices.get(&(currency.to_string(), day.to_string())).cloned()
+    }
+}
+
+/// Prices of a known set of days, loaded up front so that rendering
+/// transactions doesn't query the database per transaction and currency
+#[derive(Debug, Default)]
+pub struct DailyPrices {
+    prices: HashMap<(String, String), String>,
+}
+
+impl PriceSource for DailyPrices {
+    fn daily_price(&self, currency: &str, day: &str) -> Option<String> {
+        self.prices.get(&(currency.to_string(), day.to_string())).cloned()
+    }
+}
+
+/// Prices of `currencies` on `days`, read from the database in one query. The
+/// missing ones are asked from `fallback` and stored, so it's asked once per day.
+pub async fn load_daily_prices(pool: &DBPool, fallback: Option<&(dyn PriceSource + Sync)>, currencies: &[String], days: &[String]) -> Result<DailyPrices, diesel::result::Error> {
+    use crate::schema::eth_prices::dsl::*;
+
+    if currencies.is_empty() || days.is_empty() {
+        return Ok(DailyPrices::default());
+    }
+    let (wanted_currencies, wanted_days) = (currencies.to_vec(), days.to_vec());
+    let stored = with_conn(pool, move |conn| {
+        eth_prices
+            .filter(currency.eq_any(wanted_currencies))
+            .filter(day.eq_any(wanted_days))
+            .load::<EthPrice>(conn)
+    }).await?;
+    let mut prices: HashMap<(String, String), String> = stored
+        .into_iter()
+        .map(|stored| ((stored.currency, stored.day), stored.price))
+        .collect();
+
+    if let Some(fallback) = fallback {
+        let mut fetched = Vec::new();
+        for price_currency in currencies {
+            for price_day in days {
+                let key = (price_currency.clone(), price_day.clone());
+                if prices.contains_key(&key) {
+                    continue;
+                }
+                if let Some(fallback_price) = fallback.daily_price(price_currency, price_day) {
+                    fetched.push(EthPrice { currency: key.0.clone(), day: key.1.clone(), price: fallback_price.clone() });
+                    prices.insert(key, fallback_price);
+                }
+            }
+        }
+        if !fetched.is_empty() {
+            if let Err(e) = store_prices(pool, &fetched).await {
+                error!(error = %e, "Failed to save prices");
+            }
+        }
+    }
+
+    Ok(DailyPrices { prices })
+}
+
+fn parse_price_csv(csv: &str) -> Result<Vec<EthPrice>, String> {
+    let mut prices = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (index == 0 && line.to_lowercase().starts_with("date")) {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [day, currency, price] = fields[..] else {
+            return Err(format!("Line {}: expected `date,currency,price`", index + 1));
+        };
+        if chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").is_err() {
+            return Err(format!("Line {}: invalid date `{}`", index + 1, day));
+        }
+        if parse_decimal(price).is_none() {
+            return Err(format!("Line {}: invalid price `{}`", index + 1, price));
+        }
+        prices.push(EthPrice { currency: currency.to_uppercase(), day: day.to_string(), price: price.to_string() });
+    }
+    Ok(prices)
+}
+
+fn insert_prices(conn: &mut PgConnection, prices: &[EthPrice]) -> Result<usize, diesel::result::Error> {
+    use crate::schema::eth_prices::dsl::*;
+
+    let mut stored = 0;
+    for chunk in prices.chunks(MAX_BIND_PARAMS / 3) {
+        stored += diesel::insert_into(eth_prices)
+            .values(chunk)
+            .on_conflict((currency, day))
+            .do_update()
+            .set(price.eq(excluded(price)))
+            .execute(conn)?;
+    }
+    Ok(stored)
+}
+
+pub async fn store_prices(pool: &DBPool, prices: &[EthPrice]) -> Result<usize, diesel::result::Error> {
+    let prices = prices.to_vec();
+    with_conn(pool, move |conn| insert_prices(conn, &prices)).await
+}
+
+/// `1234.5` as (12345, 1), i.e. the digits and the number of decimals
+fn parse_decimal(value: &str) -> Option<(U256, usize)> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if integer.is_empty() || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = U256::from_dec_str(&format!("{}{}", integer, fraction)).ok()?;
+    Some((digits, fraction.len()))
+}
+
+/// Fiat value of an amount of wei at the given price, rounded to cents
+pub fn fiat_value(wei: U256, price: &str) -> Option<String> {
+    let (digits, decimals) = parse_decimal(price)?;
+    let denominator = U256::exp10(18 + decimals);
+    let cents = wei.checked_mul(digits)?.checked_mul(U256::from(100))?.checked_add(denominator / 2)? / denominator;
+    let (units, cents) = cents.div_mod(U256::from(100));
+    Some(format!("{}.{:02}", units, cents.as_u64()))
+}
+
+/// UTC day of a unix timestamp
+pub fn day_of_timestamp(timestamp: i64) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp, 0).map(|time| time.date_naive().to_string())
+}
+
+/// Value and fee of a transaction in one currency
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FiatValue {
+    pub day: String,
+    pub price: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<String>,
+}
+
+/// Fiat values in every currency a price is known for on the day of `timestamp`
+pub fn fiat_values(source: &dyn PriceSource, currencies: &[String], timestamp: i64, value: &str, fee: Option<&str>) -> BTreeMap<String, FiatValue> {
+    let Some(day) = day_of_timestamp(timestamp) else {
+        return BTreeMap::new();
+    };
+    let value = U256::from_dec_str(value).unwrap_or_default();
+    let fee = fee.and_then(|fee| U256::from_dec_str(fee).ok());
+
+    currencies
+        .iter()
+        .filter_map(|currency| {
+            let price = source.daily_price(currency, &day)?;
+            let fiat = FiatValue {
+                day: day.clone(),
+                value: fiat_value(value, &price)?,
+                fee: fee.and_then(|fee| fiat_value(fee, &price)),
+                price,
+            };
+            Some((currency.clone(), fiat))
+        })
+        .collect()
+}
+
+/// Loads a `date,currency,price` CSV body into the price table, admin only.
+/// Existing prices of the same day are replaced.
+#[post("/lime/admin/prices")]
+pub async fn load_prices(body: String, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let prices = match parse_price_csv(&body) {
+        Ok(prices) => prices,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    match store_prices(&pool, &prices).await {
+        Ok(stored) => HttpResponse::Ok().json(serde_json::json!({ "stored": stored })),
+        Err(e) => {
+            error!(error = %e, "Failed to save prices");
+            HttpResponse::InternalServerError().body("Failed to save prices")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "date,currency,price\n2024-06-30,USD,3400.50\n2024-06-30,eur,3170\n\n2024-07-01,USD,3500\n";
+
+    #[test]
+    fn test_csv_price_source() {
+        let source = CsvPriceSource::parse(CSV).unwrap();
+        assert_eq!(source.daily_price("USD", "2024-06-30").as_deref(), Some("3400.50"));
+        assert_eq!(source.daily_price("EUR", "2024-06-30").as_deref(), Some("3170"));
+        assert_eq!(source.daily_price("USD", "2024-07-02"), None);
+
+        assert!(CsvPriceSource::parse("2024-06-30,USD").is_err());
+        assert!(CsvPriceSource::parse("2024-13-01,USD,1").is_err());
+        assert!(CsvPriceSource::parse("2024-06-30,USD,1e3").is_err());
+
+        let mut config = Config::default();
+        assert_eq!(CsvPriceSource::from_config(&config).unwrap().daily_price("USD", "2024-06-30"), None);
+        config.prices.csv = Some(std::env::temp_dir().join("missing-prices.csv"));
+        assert!(CsvPriceSource::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_fiat_value() {
+        let one_ether = U256::exp10(18);
+        assert_eq!(fiat_value(one_ether, "3400.50").as_deref(), Some("3400.50"));
+        assert_eq!(fiat_value(one_ether / 2, "3400.5").as_deref(), Some("1700.25"));
+        // 21000 gas at 10 gwei
+        assert_eq!(fiat_value(U256::from(210_000_000_000_000u64), "3400").as_deref(), Some("0.71"));
+        assert_eq!(fiat_value(U256::zero(), "3400").as_deref(), Some("0.00"));
+        assert_eq!(fiat_value(one_ether, "abc"), None);
+    }
+
+    #[test]
+    fn test_fiat_values() {
+        let source = CsvPriceSource::parse(CSV).unwrap();
+        let currencies = vec!["USD".to_string(), "EUR".to_string(), "GBP".to_string()];
+        // 2024-06-30 05:48:16 UTC
+        let values = fiat_values(&source, &currencies, 1719726496, "2000000000000000000", Some("210000000000000"));
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["USD"], FiatValue {
+            day: "2024-06-30".to_string(),
+            price: "3400.50".to_string(),
+            value: "6801.00".to_string(),
+            fee: Some("0.71".to_string()),
+        });
+        assert_eq!(values["EUR"].value, "6340.00");
+    }
+}
@@ -0,0 +1,381 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use ethers::types::{Address, H256, Signature, U256};
+use ethers::types::transaction::eip2930::AccessList;
+use ethers::utils::{hex, keccak256};
+use ethers::utils::rlp::{Rlp, RlpStream, DecoderError};
+
+pub const LEGACY_TX_TYPE: u8 = 0x00;
+pub const EIP2930_TX_TYPE: u8 = 0x01;
+pub const EIP1559_TX_TYPE: u8 = 0x02;
+pub const EIP4844_TX_TYPE: u8 = 0x03;
+
+/// Fields of a signed raw transaction, decoded without broadcasting it.
+/// Fields that don't exist for the given transaction type are left out.
+#[derive(Debug, Default, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RawTransaction {
+    pub transaction_hash: String,
+    pub transaction_type: u8,
+    pub from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    pub nonce: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<String>,
+    pub gas_limit: String,
+    pub to: Option<String>,
+    pub value: String,
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<AccessList>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_blob_gas: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_versioned_hashes: Option<Vec<String>>,
+    pub v: u64,
+    pub r: String,
+    pub s: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RawTransactionError {
+    InvalidHex,
+    InvalidRlp(DecoderError),
+    UnsupportedType(u8),
+    InvalidSignature,
+    /// Byte position of the first byte after the transaction
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for RawTransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawTransactionError::InvalidHex => write!(f, "Invalid Hex String"),
+            RawTransactionError::InvalidRlp(e) => write!(f, "Invalid RLP: {}", e),
+            RawTransactionError::UnsupportedType(tx_type) => write!(f, "Unsupported transaction type: 0x{:02x}", tx_type),
+            RawTransactionError::InvalidSignature => write!(f, "Invalid Signature"),
+            RawTransactionError::TrailingBytes(offset) => write!(f, "Invalid RLP: trailing bytes after the transaction at byte {}", offset),
+        }
+    }
+}
+
+impl From<DecoderError> for RawTransactionError {
+    fn from(e: DecoderError) -> Self {
+        RawTransactionError::InvalidRlp(e)
+    }
+}
+
+pub fn decode_raw_transaction_hex(raw_hex: &str) -> Result<RawTransaction, RawTransactionError> {
+    let raw = hex::decode(raw_hex.trim()).map_err(|_| RawTransactionError::InvalidHex)?;
+    decode_raw_transaction(&raw)
+}
+
+/// Decodes a signed transaction, either a legacy RLP list or an
+/// EIP-2718 typed envelope (`type || rlp(payload)`), recovering its sender.
+pub fn decode_raw_transaction(raw: &[u8]) -> Result<RawTransaction, RawTransactionError> {
+    let first = *raw.first().ok_or(DecoderError::RlpIsTooShort)?;
+
+    // Legacy transactions are plain RLP lists, typed ones start with a byte < 0x7f
+    if first >= 0xc0 {
+        check_no_trailing_bytes(raw, 0)?;
+        return decode_legacy(raw);
+    }
+
+    check_no_trailing_bytes(&raw[1..], 1)?;
+    let payload = Rlp::new(&raw[1..]);
+    match first {
+        EIP2930_TX_TYPE | EIP1559_TX_TYPE => decode_typed(first, &payload, keccak256(raw)),
+        EIP4844_TX_TYPE => {
+            // The network form wraps the transaction as [tx_payload_body, blobs, commitments, proofs],
+            // the hash is computed over the bare transaction only
+            if payload.at(0)?.is_list() {
+                let body = payload.at(0)?;
+                let mut bare = vec![EIP4844_TX_TYPE];
+                bare.extend_from_slice(body.as_raw());
+                decode_typed(first, &body, keccak256(&bare))
+            } else {
+                decode_typed(first, &payload, keccak256(raw))
+            }
+        }
+        tx_type => Err(RawTransactionError::UnsupportedType(tx_type)),
+    }
+}
+
+/// The transaction has to be the single RLP item in `rlp_data`, which starts at `offset` of the input
+fn check_no_trailing_bytes(rlp_data: &[u8], offset: usize) -> Result<(), RawTransactionError> {
+    let info = Rlp::new(rlp_data).payload_info()?;
+    let item_len = info.header_len + info.value_len;
+    match item_len < rlp_data.len() {
+        true => Err(RawTransactionError::TrailingBytes(offset + item_len)),
+        false => Ok(()),
+    }
+}
+
+fn decode_legacy(raw: &[u8]) -> Result<RawTransaction, RawTransactionError> {
+    let rlp = Rlp::new(raw);
+    if rlp.item_count()? != 9 {
+        return Err(DecoderError::RlpIncorrectListLen.into());
+    }
+
+    let v: u64 = rlp.val_at(6)?;
+    let r: U256 = rlp.val_at(7)?;
+    let s: U256 = rlp.val_at(8)?;
+
+    // EIP-155 replay protection folds the chain id into `v`
+    let chain_id = if v >= 35 { Some((v - 35) / 2) } else { None };
+
+    let mut unsigned = RlpStream::new_list(if chain_id.is_some() { 9 } else { 6 });
+    for i in 0..6 {
+        unsigned.append_raw(rlp.at(i)?.as_raw(), 1);
+    }
+    if let Some(chain_id) = chain_id {
+        unsigned.append(&chain_id);
+        unsigned.append(&0u8);
+        unsigned.append(&0u8);
+    }
+
+    let from = recover_sender(&unsigned.out(), None, r, s, v)?;
+
+    Ok(RawTransaction {
+        transaction_hash: format!("{:?}", H256(keccak256(raw))),
+        transaction_type: LEGACY_TX_TYPE,
+        from: format!("{:?}", from),
+        chain_id,
+        nonce: rlp.val_at::<U256>(0)?.to_string(),
+        gas_price: Some(rlp.val_at::<U256>(1)?.to_string()),
+        gas_limit: rlp.val_at::<U256>(2)?.to_string(),
+        to: decode_to(&rlp, 3)?,
+        value: rlp.val_at::<U256>(4)?.to_string(),
+        input: format!("0x{}", hex::encode(rlp.val_at::<Vec<u8>>(5)?)),
+        v,
+        r: format!("{:#x}", r),
+        s: format!("{:#x}", s),
+        ..Default::default()
+    })
+}
+
+fn decode_typed(tx_type: u8, rlp: &Rlp, hash: [u8; 32]) -> Result<RawTransaction, RawTransactionError> {
+    let expected_len = match tx_type {
+        EIP2930_TX_TYPE => 11,
+        EIP1559_TX_TYPE => 12,
+        _ => 14,
+    };
+    if rlp.item_count()? != expected_len {
+        return Err(DecoderError::RlpIncorrectListLen.into());
+    }
+
+    let signature_at = expected_len - 3;
+    let v: u64 = rlp.val_at(signature_at)?;
+    let r: U256 = rlp.val_at(signature_at + 1)?;
+    let s: U256 = rlp.val_at(signature_at + 2)?;
+
+    let mut unsigned = RlpStream::new_list(signature_at);
+    for i in 0..signature_at {
+        unsigned.append_raw(rlp.at(i)?.as_raw(), 1);
+    }
+    let from = recover_sender(&unsigned.out(), Some(tx_type), r, s, v)?;
+
+    let mut tx = RawTransaction {
+        transaction_hash: format!("{:?}", H256(hash)),
+        transaction_type: tx_type,
+        from: format!("{:?}", from),
+        chain_id: Some(rlp.val_at(0)?),
+        nonce: rlp.val_at::<U256>(1)?.to_string(),
+        v,
+        r: format!("{:#x}", r),
+        s: format!("{:#x}", s),
+        ..Default::default()
+    };
+
+    // Everything after the nonce is shifted by one once the fee market fields come in
+    let fields_at = if tx_type == EIP2930_TX_TYPE {
+        tx.gas_price = Some(rlp.val_at::<U256>(2)?.to_string());
+        3
+    } else {
+        tx.max_priority_fee_per_gas = Some(rlp.val_at::<U256>(2)?.to_string());
+        tx.max_fee_per_gas = Some(rlp.val_at::<U256>(3)?.to_string());
+        4
+    };
+
+    tx.gas_limit = rlp.val_at::<U256>(fields_at)?.to_string();
+    tx.to = decode_to(rlp, fields_at + 1)?;
+    tx.value = rlp.val_at::<U256>(fields_at + 2)?.to_string();
+    tx.input = format!("0x{}", hex::encode(rlp.val_at::<Vec<u8>>(fields_at + 3)?));
+    tx.access_list = Some(rlp.val_at(fields_at + 4)?);
+
+    if tx_type == EIP4844_TX_TYPE {
+        tx.max_fee_per_blob_gas = Some(rlp.val_at::<U256>(fields_at + 5)?.to_string());
+        let blob_hashes: Vec<H256> = rlp.list_at(fields_at + 6)?;
+        tx.blob_versioned_hashes = Some(blob_hashes.iter().map(|hash| format!("{:?}", hash)).collect());
+    }
+
+    Ok(tx)
+}
+
+/// Empty `to` means contract creation
+fn decode_to(rlp: &Rlp, index: usize) -> Result<Option<String>, DecoderError> {
+    let to = rlp.at(index)?;
+    if to.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("{:?}", to.as_val::<Address>()?)))
+    }
+}
+
+fn recover_sender(unsigned_rlp: &[u8], tx_type: Option<u8>, r: U256, s: U256, v: u64) -> Result<Address, RawTransactionError> {
+    let mut preimage = Vec::with_capacity(unsigned_rlp.len() + 1);
+    if let Some(tx_type) = tx_type {
+        preimage.push(tx_type);
+    }
+    preimage.extend_from_slice(unsigned_rlp);
+
+    let signature = Signature { r, s, v };
+    signature
+        .recover(H256(keccak256(&preimage)))
+        .map_err(|_| RawTransactionError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest};
+    use ethers::types::transaction::eip2718::TypedTransaction;
+    use ethers::types::transaction::eip2930::{AccessListItem, Eip2930TransactionRequest};
+
+    const PRIVATE_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn wallet() -> LocalWallet {
+        PRIVATE_KEY.parse::<LocalWallet>().unwrap().with_chain_id(11155111u64)
+    }
+
+    fn to() -> Address {
+        "0x2222222222222222222222222222222222222222".parse().unwrap()
+    }
+
+    fn sign(tx: TypedTransaction) -> (Vec<u8>, H256) {
+        let wallet = wallet();
+        let signature = wallet.sign_transaction_sync(&tx).unwrap();
+        (tx.rlp_signed(&signature).to_vec(), tx.hash(&signature))
+    }
+
+    #[test]
+    fn test_decode_raw_legacy() {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(to()).value(1000).gas(21000).gas_price(10).nonce(7).chain_id(11155111u64).into();
+        let (raw, hash) = sign(tx);
+
+        let decoded = decode_raw_transaction(&raw).unwrap();
+        assert_eq!(decoded.transaction_type, LEGACY_TX_TYPE);
+        assert_eq!(decoded.transaction_hash, format!("{:?}", hash));
+        assert_eq!(decoded.from, format!("{:?}", wallet().address()));
+        assert_eq!(decoded.chain_id, Some(11155111));
+        assert_eq!(decoded.nonce, "7");
+        assert_eq!(decoded.gas_price.as_deref(), Some("10"));
+        assert_eq!(decoded.value, "1000");
+    }
+
+    #[test]
+    fn test_decode_raw_eip2930() {
+        let access_list = AccessList(vec![AccessListItem { address: to(), storage_keys: vec![H256::zero()] }]);
+        let request = TransactionRequest::new()
+            .to(to()).value(1).gas(30000).gas_price(12).nonce(1).chain_id(11155111u64);
+        let tx: TypedTransaction = Eip2930TransactionRequest::new(request, access_list.clone()).into();
+        let (raw, hash) = sign(tx);
+
+        let decoded = decode_raw_transaction(&raw).unwrap();
+        assert_eq!(decoded.transaction_type, EIP2930_TX_TYPE);
+        assert_eq!(decoded.transaction_hash, format!("{:?}", hash));
+        assert_eq!(decoded.from, format!("{:?}", wallet().address()));
+        assert_eq!(decoded.access_list, Some(access_list));
+    }
+
+    #[test]
+    fn test_decode_raw_eip1559() {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(to()).value(5).gas(21000).max_fee_per_gas(100).max_priority_fee_per_gas(2).nonce(3)
+            .data(vec![0xa9, 0x05, 0x9c, 0xbb]).chain_id(11155111u64).into();
+        let (raw, hash) = sign(tx);
+
+        let decoded = decode_raw_transaction_hex(&format!("0x{}", hex::encode(&raw))).unwrap();
+        assert_eq!(decoded.transaction_type, EIP1559_TX_TYPE);
+        assert_eq!(decoded.transaction_hash, format!("{:?}", hash));
+        assert_eq!(decoded.from, format!("{:?}", wallet().address()));
+        assert_eq!(decoded.max_fee_per_gas.as_deref(), Some("100"));
+        assert_eq!(decoded.max_priority_fee_per_gas.as_deref(), Some("2"));
+        assert_eq!(decoded.input, "0xa9059cbb");
+    }
+
+    #[test]
+    fn test_decode_raw_eip4844() {
+        let blob_hash = H256::repeat_byte(0x01);
+
+        let mut unsigned = RlpStream::new_list(11);
+        unsigned.append(&11155111u64);
+        unsigned.append(&U256::from(9));
+        unsigned.append(&U256::from(2));
+        unsigned.append(&U256::from(100));
+        unsigned.append(&U256::from(21000));
+        unsigned.append(&to());
+        unsigned.append(&U256::zero());
+        unsigned.append(&Vec::<u8>::new());
+        unsigned.append(&AccessList::default());
+        unsigned.append(&U256::from(3));
+        unsigned.append_list(&[blob_hash]);
+
+        let unsigned_rlp = unsigned.out();
+        let mut preimage = vec![EIP4844_TX_TYPE];
+        preimage.extend_from_slice(&unsigned_rlp);
+        let signature = wallet().sign_hash(H256(keccak256(&preimage))).unwrap();
+
+        let mut signed = RlpStream::new_list(14);
+        for item in Rlp::new(&unsigned_rlp).iter() {
+            signed.append_raw(item.as_raw(), 1);
+        }
+        signed.append(&(signature.v - 27));
+        signed.append(&signature.r);
+        signed.append(&signature.s);
+
+        let mut raw = vec![EIP4844_TX_TYPE];
+        raw.extend_from_slice(&signed.out());
+
+        let decoded = decode_raw_transaction(&raw).unwrap();
+        assert_eq!(decoded.transaction_type, EIP4844_TX_TYPE);
+        assert_eq!(decoded.transaction_hash, format!("{:?}", H256(keccak256(&raw))));
+        assert_eq!(decoded.from, format!("{:?}", wallet().address()));
+        assert_eq!(decoded.max_fee_per_blob_gas.as_deref(), Some("3"));
+        assert_eq!(decoded.blob_versioned_hashes, Some(vec![format!("{:?}", blob_hash)]));
+    }
+
+    #[test]
+    fn test_decode_raw_trailing_bytes() {
+        let legacy: TypedTransaction = TransactionRequest::new()
+            .to(to()).value(1000).gas(21000).gas_price(10).nonce(7).chain_id(11155111u64).into();
+        let (mut raw, _) = sign(legacy);
+        let len = raw.len();
+        raw.push(0x00);
+        assert_eq!(decode_raw_transaction(&raw), Err(RawTransactionError::TrailingBytes(len)));
+
+        let typed: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(to()).value(5).gas(21000).max_fee_per_gas(100).max_priority_fee_per_gas(2).nonce(3).chain_id(11155111u64).into();
+        let (mut raw, _) = sign(typed);
+        let len = raw.len();
+        raw.extend_from_slice(&[0xde, 0xad]);
+        assert_eq!(decode_raw_transaction(&raw), Err(RawTransactionError::TrailingBytes(len)));
+    }
+
+    #[test]
+    fn test_decode_raw_invalid() {
+        assert_eq!(decode_raw_transaction_hex("zz"), Err(RawTransactionError::InvalidHex));
+        assert_eq!(decode_raw_transaction(&[0x05, 0xc0]), Err(RawTransactionError::UnsupportedType(0x05)));
+        assert!(matches!(decode_raw_transaction(&[0x02, 0xc0]), Err(RawTransactionError::InvalidRlp(_))));
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::header::ACCEPT;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use ethers::types::{Address, U256};
+use ethers::utils::{format_units, to_checksum};
+use tracing::error;
+
+use crate::DBPool;
+use crate::abi::{DecodedCall, decode_input, get_contract_abis};
+use crate::blocks::get_block_timestamps;
+use crate::export::{CSV_CONTENT_TYPE, NDJSON_CONTENT_TYPE, columns_from_request, content_type, csv_header, render_rows};
+use crate::events::{DecodedEvent, decode_logs, get_transaction_logs};
+use crate::config::Config;
+use crate::prices::{CsvPriceSource, FiatValue, day_of_timestamp, fiat_values, load_daily_prices};
+use crate::transaction::{Transaction, encode_transactions_rlp, get_transaction_fees};
+
+pub const RLP_CONTENT_TYPE: &str = "application/x-rlp";
+
+/// `format` query parameter shared by the lookup routes
+#[derive(Debug, Deserialize)]
+pub struct FormatParams {
+    pub format: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Rlp,
+    Csv,
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// The `format` query parameter wins over the `Accept` header, JSON is the default
+    pub fn from_request(req: &HttpRequest) -> Result<Self, String> {
+        let format = web::Query::<FormatParams>::from_query(req.query_string())
+            .ok()
+            .and_then(|params| params.into_inner().format);
+
+        if let Some(format) = format {
+            return match format.as_str() {
+                "json" => Ok(OutputFormat::Json),
+                "rlp" => Ok(OutputFormat::Rlp),
+                "csv" => Ok(OutputFormat::Csv),
+                "ndjson" => Ok(OutputFormat::Ndjson),
+                _ => Err(format!("Unsupported format: {}", format)),
+            };
+        }
+
+        let accept = req.headers()
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if accept.contains(RLP_CONTENT_TYPE) {
+            Ok(OutputFormat::Rlp)
+        } else if accept.contains(CSV_CONTENT_TYPE) {
+            Ok(OutputFormat::Csv)
+        } else if accept.contains(NDJSON_CONTENT_TYPE) {
+            Ok(OutputFormat::Ndjson)
+        } else {
+            Ok(OutputFormat::Json)
+        }
+    }
+}
+
+/// Unit of the wei amounts (`value`, `fee`) in JSON responses
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Unit {
+    #[default]
+    Wei,
+    Gwei,
+    Ether,
+}
+
+/// Encoding of integers (`blockNumber`, `logsCount`, `blockTimestamp`) and wei amounts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NumberEncoding {
+    /// Integers as JSON numbers, amounts as decimal strings
+    #[default]
+    Decimal,
+    /// `0x` prefixed hex strings, as in the JSON-RPC API
+    Hex,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AddressCase {
+    #[default]
+    Lowercase,
+    /// EIP-55 mixed case checksum
+    Checksum,
+}
+
+/// `unit`, `numbers` and `addresses` query parameters shared by every route returning transactions
+#[derive(Debug, Deserialize)]
+pub struct ResponseOptionParams {
+    pub unit: Option<String>,
+    pub numbers: Option<String>,
+    pub addresses: Option<String>,
+}
+
+/// How transactions are represented in JSON responses
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResponseOptions {
+    pub unit: Unit,
+    pub numbers: NumberEncoding,
+    pub addresses: AddressCase,
+}
+
+const AMOUNT_FIELDS: &[&str] = &["value", "fee"];
+const INTEGER_FIELDS: &[&str] = &["blockNumber", "logsCount", "blockTimestamp"];
+const ADDRESS_FIELDS: &[&str] = &["from", "to", "contractAddress"];
+
+impl ResponseOptions {
+    pub fn from_request(req: &HttpRequest) -> Result<Self, String> {
+        Self::from_query(req.query_string())
+    }
+
+    /// Unset parameters keep the defaults, unknown values are an error
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let params = web::Query::<ResponseOptionParams>::from_query(query)
+            .map(|params| params.into_inner())
+            .map_err(|_| "Invalid response options".to_string())?;
+
+        let unit = match params.unit.as_deref() {
+            None | Some("wei") => Unit::Wei,
+            Some("gwei") => Unit::Gwei,
+            Some("ether") => Unit::Ether,
+            Some(unit) => return Err(format!("Unsupported unit: {}", unit)),
+        };
+        let numbers = match params.numbers.as_deref() {
+            None | Some("decimal") => NumberEncoding::Decimal,
+            Some("hex") => NumberEncoding::Hex,
+            Some(numbers) => return Err(format!("Unsupported number encoding: {}", numbers)),
+        };
+        let addresses = match params.addresses.as_deref() {
+            None | Some("lowercase") => AddressCase::Lowercase,
+            Some("checksum") => AddressCase::Checksum,
+            Some(addresses) => return Err(format!("Unsupported address casing: {}", addresses)),
+        };
+        if numbers == NumberEncoding::Hex && unit != Unit::Wei {
+            return Err("Hex numbers are only supported with unit=wei".to_string());
+        }
+
+        Ok(ResponseOptions { unit, numbers, addresses })
+    }
+
+    /// Serializes a transaction (or anything flattening one) and rewrites its
+    /// amounts, integers and addresses as requested
+    pub fn format_transaction<T: Serialize>(&self, transaction: &T) -> Value {
+        let mut value = serde_json::to_value(transaction).unwrap_or(Value::Null);
+        let Some(fields) = value.as_object_mut() else {
+            return value;
+        };
+
+        for field in AMOUNT_FIELDS {
+            if let Some(amount) = fields.get_mut(*field) {
+                if let Some(formatted) = amount.as_str().and_then(|wei| self.format_amount(wei)) {
+                    *amount = Value::String(formatted);
+                }
+            }
+        }
+        if self.numbers == NumberEncoding::Hex {
+            for field in INTEGER_FIELDS {
+                if let Some(integer) = fields.get_mut(*field) {
+                    if let Some(n) = integer.as_u64() {
+                        *integer = Value::String(format!("{:#x}", n));
+                    }
+                }
+            }
+        }
+        if self.addresses == AddressCase::Checksum {
+            for field in ADDRESS_FIELDS {
+                if let Some(address) = fields.get_mut(*field) {
+                    if let Some(checksummed) = address.as_str().and_then(|a| Address::from_str(a).ok()).map(|a| to_checksum(&a, None)) {
+                        *address = Value::String(checksummed);
+                    }
+                }
+            }
+        }
+
+        value
+    }
+
+    pub fn format_transactions<T: Serialize>(&self, transactions: &[T]) -> Vec<Value> {
+        transactions.iter().map(|transaction| self.format_transaction(transaction)).collect()
+    }
+
+    fn format_amount(&self, wei: &str) -> Option<String> {
+        let wei = U256::from_dec_str(wei).ok()?;
+        match (self.unit, self.numbers) {
+            (Unit::Wei, NumberEncoding::Hex) => Some(format!("{:#x}", wei)),
+            (Unit::Wei, NumberEncoding::Decimal) => Some(wei.to_string()),
+            (Unit::Gwei, _) => format_units(wei, "gwei").ok().map(trim_fraction),
+            (Unit::Ether, _) => format_units(wei, "ether").ok().map(trim_fraction),
+        }
+    }
+}
+
+/// `1.500000000` as `1.5`, `2.000000000` as `2`
+fn trim_fraction(amount: String) -> String {
+    if !amount.contains('.') {
+        return amount;
+    }
+    amount.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// A transaction as returned by the lookup routes: the stored fields plus
+/// whatever could be derived from them
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionView {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    /// Unix time in seconds of the block, when the block is stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_timestamp: Option<i64>,
+    /// Fee paid in wei
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<String>,
+    /// Value and fee by currency, for the currencies a price is known for on the day of the block
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fiat: BTreeMap<String, FiatValue>,
+    pub decoded_input: Option<DecodedCall>,
+    pub decoded_events: Vec<DecodedEvent>,
+}
+
+pub async fn transaction_views(pool: &DBPool, config: &Config, csv_prices: &CsvPriceSource, transactions: Vec<Transaction>) -> Vec<TransactionView> {
+    let contract_addresses: Vec<String> = transactions.iter().filter_map(|tx| tx.to.clone()).collect();
+    let abis = match get_contract_abis(pool, &contract_addresses).await {
+        Ok(abis) => abis,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch contract ABIs");
+            Default::default()
+        }
+    };
+
+    let tx_hashes: Vec<String> = transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+    let logs = match get_transaction_logs(pool, &tx_hashes).await {
+        Ok(logs) => logs,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch transaction logs");
+            Vec::new()
+        }
+    };
+    let block_hashes: Vec<String> = transactions.iter().map(|tx| tx.block_hash.clone()).collect();
+    let block_timestamps = match get_block_timestamps(pool, &block_hashes).await {
+        Ok(timestamps) => timestamps,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch block timestamps");
+            Default::default()
+        }
+    };
+
+    let fees = match get_transaction_fees(pool, &tx_hashes).await {
+        Ok(fees) => fees,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch transaction fees");
+            Default::default()
+        }
+    };
+    let mut events_by_tx: HashMap<String, Vec<DecodedEvent>> = HashMap::new();
+    for event in decode_logs(pool, &logs).await {
+        events_by_tx.entry(event.transaction_hash.clone()).or_default().push(event);
+    }
+
+    let currencies = &config.prices.fiat_currencies;
+    let mut days: Vec<String> = block_timestamps.values().filter_map(|timestamp| day_of_timestamp(*timestamp)).collect();
+    days.sort();
+    days.dedup();
+    let prices = match load_daily_prices(pool, Some(csv_prices), currencies, &days).await {
+        Ok(prices) => prices,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch prices");
+            Default::default()
+        }
+    };
+
+    transactions
+        .into_iter()
+        .map(|transaction| {
+            let abi = transaction.to.as_ref().and_then(|to| abis.get(to));
+            let block_timestamp = block_timestamps.get(&transaction.block_hash).copied();
+            let fee = fees.get(&transaction.transaction_hash).cloned();
+            let fiat = block_timestamp
+                .map(|timestamp| fiat_values(&prices, currencies, timestamp, &transaction.value, fee.as_deref()))
+                .unwrap_or_default();
+            TransactionView {
+                block_timestamp,
+                fee,
+                fiat,
+                decoded_input: decode_input(abi, &transaction.input),
+                decoded_events: events_by_tx.remove(&transaction.transaction_hash).unwrap_or_default(),
+                transaction,
+            }
+        })
+        .collect()
+}
+
+/// Renders the result of a lookup in the format requested by the client
+pub async fn transactions_response(req: &HttpRequest, pool: &DBPool, config: &Config, csv_prices: &CsvPriceSource, transactions: Vec<Transaction>) -> HttpResponse {
+    match OutputFormat::from_request(req) {
+        Ok(OutputFormat::Json) => {
+            let options = match ResponseOptions::from_request(req) {
+                Ok(options) => options,
+                Err(e) => return HttpResponse::BadRequest().body(e),
+            };
+            let transactions = transaction_views(pool, config, csv_prices, transactions).await;
+            let response = serde_json::json!({ "transactions": options.format_transactions(&transactions) });
+            HttpResponse::Ok().json(response)
+        }
+        Ok(format) => rows_response(req, format, &transactions),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+/// Same as `transactions_response`, without the DB backed details of the JSON views
+pub fn uncached_transactions_response(req: &HttpRequest, transactions: Vec<Transaction>) -> HttpResponse {
+    match OutputFormat::from_request(req) {
+        Ok(OutputFormat::Json) => {
+            let options = match ResponseOptions::from_request(req) {
+                Ok(options) => options,
+                Err(e) => return HttpResponse::BadRequest().body(e),
+            };
+            let response = serde_json::json!({ "transactions": options.format_transactions(&transactions) });
+            HttpResponse::Ok().json(response)
+        }
+        Ok(format) => rows_response(req, format, &transactions),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+/// RLP, CSV and NDJSON renderings, which only need the transactions themselves
+fn rows_response(req: &HttpRequest, format: OutputFormat, transactions: &[Transaction]) -> HttpResponse {
+    if format == OutputFormat::Rlp {
+        return match encode_transactions_rlp(transactions) {
+            Ok(body) => HttpResponse::Ok().content_type(RLP_CONTENT_TYPE).body(body),
+            Err(e) => {
+                error!(error = %e, "Failed to encode transactions");
+                HttpResponse::InternalServerError().body("Failed to encode transactions")
+            }
+        };
+    }
+    let options = match ResponseOptions::from_request(req) {
+        Ok(options) => options,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let columns = match columns_from_request(req) {
+        Ok(columns) => columns,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut body = match format {
+        OutputFormat::Csv => csv_header(&columns),
+        _ => String::new(),
+    };
+    body.push_str(&render_rows(&format, &columns, &options, transactions));
+    HttpResponse::Ok().content_type(content_type(&format)).body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_transaction;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_output_format_from_request() {
+        let req = TestRequest::get().uri("/lime/eth?format=rlp").to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Rlp));
+
+        let req = TestRequest::get().uri("/lime/eth").insert_header((ACCEPT, RLP_CONTENT_TYPE)).to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Rlp));
+
+        let req = TestRequest::get().uri("/lime/eth?format=json").insert_header((ACCEPT, RLP_CONTENT_TYPE)).to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Json));
+
+        let req = TestRequest::get().uri("/lime/eth").to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Json));
+
+        let req = TestRequest::get().uri("/lime/all?format=ndjson").insert_header((ACCEPT, CSV_CONTENT_TYPE)).to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Ndjson));
+
+        let req = TestRequest::get().uri("/lime/all").insert_header((ACCEPT, CSV_CONTENT_TYPE)).to_http_request();
+        assert_eq!(OutputFormat::from_request(&req), Ok(OutputFormat::Csv));
+
+        let req = TestRequest::get().uri("/lime/eth?format=xml").to_http_request();
+        assert!(OutputFormat::from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_response_options_from_query() {
+        assert_eq!(ResponseOptions::from_query(""), Ok(ResponseOptions::default()));
+        assert_eq!(
+            ResponseOptions::from_query("unit=ether&addresses=checksum&transactionHashes=0x01"),
+            Ok(ResponseOptions { unit: Unit::Ether, numbers: NumberEncoding::Decimal, addresses: AddressCase::Checksum })
+        );
+        assert!(ResponseOptions::from_query("unit=finney").is_err());
+        assert!(ResponseOptions::from_query("numbers=octal").is_err());
+        assert!(ResponseOptions::from_query("addresses=upper").is_err());
+        assert!(ResponseOptions::from_query("unit=gwei&numbers=hex").is_err());
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            from: "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            to: Some("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359".to_string()),
+            logs_count: 1,
+            value: "1500000000000000000".to_string(),
+            ..test_transaction("0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e", "0xabababababababababababababababababababababababababababababababab", 5703601)
+        }
+    }
+
+    #[test]
+    fn test_format_transaction() {
+        let formatted = ResponseOptions::default().format_transaction(&transaction());
+        assert_eq!(formatted, serde_json::to_value(transaction()).unwrap());
+
+        let options = ResponseOptions { unit: Unit::Ether, numbers: NumberEncoding::Decimal, addresses: AddressCase::Checksum };
+        let formatted = options.format_transaction(&transaction());
+        assert_eq!(formatted["value"], "1.5");
+        assert_eq!(formatted["blockNumber"], 5703601);
+        assert_eq!(formatted["from"], "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(formatted["to"], "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359");
+        assert_eq!(formatted["contractAddress"], Value::Null);
+
+        let options = ResponseOptions { unit: Unit::Gwei, ..Default::default() };
+        assert_eq!(options.format_transaction(&transaction())["value"], "1500000000");
+
+        let options = ResponseOptions { numbers: NumberEncoding::Hex, ..Default::default() };
+        let formatted = options.format_transaction(&transaction());
+        assert_eq!(formatted["value"], "0x14d1120d7b160000");
+        assert_eq!(formatted["blockNumber"], "0x5707b1");
+        assert_eq!(formatted["logsCount"], "0x1");
+        assert_eq!(formatted["transactionHash"], transaction().transaction_hash);
+    }
+}
@@ -1,139 +1,420 @@
-use std::{str::FromStr};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
 
-use actix_web::{web, get, Responder, HttpResponse, HttpRequest};
+use actix_web::{web, get, post, Responder, HttpResponse, HttpRequest};
+use actix_web::http::header::CONTENT_TYPE;
 use actix_web_lab::extract::Query;
+use diesel::Connection;
+use serde::Deserialize;
 
+use ethers::providers::Middleware;
 use ethers::types::H256;
+use tracing::{error, warn};
 
 use crate::transaction::{
     Transaction,
+    EthProvider,
+    TransactionFee,
     TransactionHashes,
+    FetchedTransaction,
+    NodeTransaction,
     DecodeError,
-    store_transaction_in_db,
+    insert_transactions,
+    insert_transaction_fees,
     get_transaction_from_db,
+    get_transactions_from_db,
     get_all_transactions_from_db,
     fetch_transaction,
     decode_rlp_hex,
+    decode_rlp_bytes,
+    transaction_exists_on_chain,
 };
+use crate::blocks::capture_block;
+use crate::cache::{TransactionCache, get_unknown_transactions, insert_unknown_transactions};
+use crate::db::with_conn;
+use crate::events::{TransactionLog, insert_transaction_logs};
+use crate::tokens::{insert_token_transfers, token_transfers_from_logs};
+use crate::traces::capture_transaction_trace;
+use crate::raw_transaction::{decode_raw_transaction, decode_raw_transaction_hex};
+use crate::metrics::{record_cache_lookup, record_unknown_lookup};
+use crate::export::{ExportSource, export_response};
+use crate::response::{OutputFormat, transactions_response, uncached_transactions_response};
+use crate::setup::database_available;
 use crate::users::{
     get_user_from_token,
-    store_user_search,
+    insert_user_searches,
     get_user_search_transactions,
 };
+use crate::config::Config;
+use crate::prices::CsvPriceSource;
 use crate::DBPool;
 
-#[get("/lime/eth")]
-async fn lime_eth_transactions_hashes(query: Query<TransactionHashes>, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
-    let TransactionHashes { hashes } = query.into_inner();
-    let user = get_user_from_token(&req).await;
+/// Everything a lookup writes, in a single database transaction so a request
+/// is stored completely or not at all: the transactions fetched from the node
+/// along with their fees, logs and token transfers, the searches of `user` and
+/// the hashes the node didn't know
+async fn store_lookup(pool: &DBPool, fetched: &[FetchedTransaction], user: Option<&str>, found: &[String], not_found: &[String], unknown_ttl: Duration) -> Result<(), diesel::result::Error> {
+    let transactions: Vec<Transaction> = fetched.iter().map(|fetched| fetched.transaction.clone()).collect();
+    let fees: Vec<TransactionFee> = fetched.iter().map(|fetched| fetched.fee.clone()).collect();
+    let logs: Vec<TransactionLog> = fetched.iter().flat_map(|fetched| fetched.logs.iter().cloned()).collect();
+    let transfers = token_transfers_from_logs(&logs);
+    let search = user.map(|user| (user.to_string(), found.to_vec()));
+    let not_found = not_found.to_vec();
 
-    let mut transactions : Vec<Transaction> = Vec::new();
+    with_conn(pool, move |conn| {
+        conn.transaction(|conn| {
+            insert_transactions(conn, &transactions)?;
+            insert_transaction_fees(conn, &fees)?;
+            insert_transaction_logs(conn, &logs)?;
+            insert_token_transfers(conn, &transfers)?;
+            if let Some((user, found)) = search {
+                insert_user_searches(conn, &user, &found)?;
+            }
+            if !not_found.is_empty() {
+                insert_unknown_transactions(conn, &not_found, unknown_ttl)?;
+            }
+            Ok(())
+        })
+    }).await
+}
 
-    for hash_str in &hashes {
-        let mut transaction_found = true;
+/// Resolves transactions the same way for every lookup route: the in-memory
+/// cache first, then the local DB, then the ETH node (caching whatever it
+/// returns). Hashes the node didn't know within `cache.negative_ttl_secs` are
+/// not fetched again unless `refresh` is set. Transactions that aren't found
+/// are left out, found ones are recorded in the searches of `user`, if any.
+pub async fn lookup_transactions(pool: &web::Data<DBPool>, config: &Config, provider: &EthProvider, cache: &TransactionCache, hashes: &[H256], refresh: bool, user: Option<&str>) -> Vec<Transaction> {
+    let tx_hashes: Vec<String> = hashes.iter().map(|hash| format!("{:?}", hash)).collect();
+    let mut cached: HashMap<String, Transaction> = tx_hashes.iter()
+        .filter_map(|tx_hash| cache.get(tx_hash).map(|tx| (tx_hash.clone(), tx)))
+        .collect();
 
-        if let Ok(Some(tx)) = get_transaction_from_db(&pool, hash_str).await {
-            transaction_found = true;
-            transactions.push(tx);
-        } else {
-            if let Ok(tx_hash) = H256::from_str(hash_str) {
-                if let Some(tx) = fetch_transaction(tx_hash).await {
-                    if let Err(e) = store_transaction_in_db(pool.clone(), &tx).await {
-                        // Shouldn't happen!!
-                        eprintln!("Failed to save transaction: {}", e);
+    let uncached: Vec<String> = tx_hashes.iter().filter(|tx_hash| !cached.contains_key(*tx_hash)).cloned().collect();
+    if !uncached.is_empty() && cache.needs_head() {
+        // Only transactions deep enough below the head are kept in memory
+        match provider.get_block_number().await {
+            Ok(head) => cache.observe_head(head.as_u64()),
+            Err(e) => warn!(error = %e, "Failed to fetch the chain head"),
+        }
+    }
+    if !uncached.is_empty() {
+        match get_transactions_from_db(pool, &uncached).await {
+            Ok(transactions) => {
+                for tx in transactions {
+                    cache.insert(&tx);
+                    cached.insert(tx.transaction_hash.clone(), tx);
+                }
+            }
+            Err(e) => error!(error = %e, "Failed to fetch transactions"),
+        }
+    }
+
+    let mut unknown: HashSet<String> = HashSet::new();
+    if !refresh {
+        let (known_unknown, uncached): (Vec<String>, Vec<String>) = tx_hashes.iter()
+            .filter(|tx_hash| !cached.contains_key(*tx_hash))
+            .cloned()
+            .partition(|tx_hash| cache.is_unknown(tx_hash));
+        unknown.extend(known_unknown);
+        if config.negative_table() && !uncached.is_empty() {
+            match get_unknown_transactions(pool, &uncached, config.negative_cache_ttl()).await {
+                Ok(tx_hashes) => {
+                    for tx_hash in tx_hashes {
+                        cache.insert_unknown(&tx_hash);
+                        unknown.insert(tx_hash);
                     }
-                    transaction_found = true;
-                    transactions.push(tx);
                 }
+                Err(e) => error!(error = %e, "Failed to fetch unknown transactions"),
+            }
+        }
+    }
+
+    let mut fetched: Vec<FetchedTransaction> = Vec::new();
+    let mut not_found: Vec<String> = Vec::new();
+    let mut transactions: Vec<Transaction> = Vec::new();
+    for (hash, tx_hash) in hashes.iter().zip(&tx_hashes) {
+        if let Some(tx) = cached.get(tx_hash) {
+            record_cache_lookup(true);
+            transactions.push(tx.clone());
+            continue;
+        }
+        if unknown.contains(tx_hash) {
+            record_unknown_lookup();
+            continue;
+        }
+        record_cache_lookup(false);
+        // A hash repeated in the request is only fetched once
+        match fetch_transaction(provider, *hash).await {
+            Ok(NodeTransaction::Mined(fetched_tx)) => {
+                transactions.push(fetched_tx.transaction.clone());
+                cached.insert(tx_hash.clone(), fetched_tx.transaction.clone());
+                fetched.push(*fetched_tx);
             }
-            else {
-                return HttpResponse::BadRequest().body("Invalid Transaction Hash provided!");
+            // Left out like an unknown one, but asked again on the next lookup
+            Ok(NodeTransaction::Pending) => {}
+            Ok(NodeTransaction::Unknown) => {
+                cache.insert_unknown(tx_hash);
+                unknown.insert(tx_hash.clone());
+                not_found.push(tx_hash.clone());
             }
+            Err(e) => warn!(error = %e, tx_hash, "Failed to fetch transaction"),
         }
+    }
+    if !config.negative_table() {
+        not_found.clear();
+    }
 
-        if transaction_found {
-            if let Some(ref user) = user {
-                if let Err(e) = store_user_search(pool.clone(), user, hash_str).await {
-                    eprintln!("Failed to save user search: {}", e);
-                }
+    if fetched.is_empty() && not_found.is_empty() && user.is_none() {
+        return transactions;
+    }
+    let found: Vec<String> = transactions.iter().map(|tx| tx.transaction_hash.clone()).collect();
+    match store_lookup(pool, &fetched, user, &found, &not_found, config.negative_cache_ttl()).await {
+        Ok(()) => {
+            for fetched_tx in &fetched {
+                // Only once stored, so memory never serves what the database doesn't have
+                cache.insert(&fetched_tx.transaction);
             }
+            spawn_captures(pool, config, provider, &fetched);
         }
+        Err(e) => error!(error = %e, transactions = fetched.len(), "Failed to save transactions"),
     }
 
-    let response = serde_json::json!({ "transactions": transactions });
+    transactions
+}
 
-    HttpResponse::Ok().json(response)
+/// Stores the blocks and call traces of newly fetched transactions without
+/// holding up the lookup, they take one or two more node requests each
+fn spawn_captures(pool: &web::Data<DBPool>, config: &Config, provider: &EthProvider, fetched: &[FetchedTransaction]) {
+    let mut block_hashes: Vec<String> = fetched.iter().map(|fetched| fetched.transaction.block_hash.clone()).collect();
+    block_hashes.sort();
+    block_hashes.dedup();
+    let tx_hashes: Vec<H256> = fetched.iter().filter_map(|fetched| H256::from_str(&fetched.transaction.transaction_hash).ok()).collect();
+
+    let (pool, config, provider) = (pool.clone(), config.clone(), provider.clone());
+    actix_web::rt::spawn(async move {
+        for block_hash in &block_hashes {
+            capture_block(&pool, &provider, block_hash).await;
+        }
+        for tx_hash in tx_hashes {
+            capture_transaction_trace(&pool, &config, &provider, tx_hash).await;
+        }
+    });
+}
+
+/// `lookup_transactions` for a single hash
+pub async fn lookup_transaction(pool: &web::Data<DBPool>, config: &Config, provider: &EthProvider, cache: &TransactionCache, tx_hash: H256, refresh: bool, user: Option<&str>) -> Option<Transaction> {
+    lookup_transactions(pool, config, provider, cache, &[tx_hash], refresh, user).await.pop()
+}
+
+/// `refresh` query parameter of the lookup routes
+#[derive(Debug, Deserialize)]
+struct RefreshParams {
+    #[serde(default)]
+    refresh: bool,
+}
+
+/// Whether `refresh=true` asks to fetch hashes remembered as unknown again
+pub fn refresh_requested(req: &HttpRequest) -> bool {
+    web::Query::<RefreshParams>::from_query(req.query_string()).is_ok_and(|params| params.refresh)
+}
+
+fn parse_transaction_hashes(hash_strs: &[String]) -> Option<Vec<H256>> {
+    hash_strs.iter().map(|hash_str| H256::from_str(hash_str).ok()).collect()
+}
+
+#[get("/lime/eth")]
+async fn lime_eth_transactions_hashes(query: Query<TransactionHashes>, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, csv_prices: web::Data<CsvPriceSource>, req: HttpRequest) -> impl Responder {
+    let TransactionHashes { hashes } = query.into_inner();
+    let user = match get_user_from_token(&req).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+
+    let hashes = match parse_transaction_hashes(&hashes) {
+        Some(hashes) => hashes,
+        None => return HttpResponse::BadRequest().body("Invalid Transaction Hash provided!"),
+    };
+
+    if !database_available(&req) {
+        // Degraded mode, from memory or straight from the node without storing or recording the search
+        let refresh = refresh_requested(&req);
+        let mut transactions = Vec::new();
+        for hash in hashes {
+            let tx_hash = format!("{:?}", hash);
+            if let Some(tx) = cache.get(&tx_hash) {
+                transactions.push(tx);
+                continue;
+            }
+            if !refresh && cache.is_unknown(&tx_hash) {
+                continue;
+            }
+            match fetch_transaction(&provider, hash).await {
+                Ok(NodeTransaction::Mined(fetched)) => transactions.push(fetched.transaction),
+                Ok(NodeTransaction::Pending) => {}
+                Ok(NodeTransaction::Unknown) => cache.insert_unknown(&tx_hash),
+                Err(e) => warn!(error = %e, tx_hash, "Failed to fetch transaction"),
+            }
+        }
+        return uncached_transactions_response(&req, transactions);
+    }
+
+    let transactions = lookup_transactions(&pool, &config, &provider, &cache, &hashes, refresh_requested(&req), user.as_deref()).await;
+    transactions_response(&req, &pool, &config, &csv_prices, transactions).await
 }
 
 #[get("/lime/all")]
-pub async fn lime_all(pool: web::Data<DBPool>) -> impl Responder {
+pub async fn lime_all(pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
+    match OutputFormat::from_request(&req) {
+        Ok(format @ (OutputFormat::Csv | OutputFormat::Ndjson)) => return export_response(&req, &pool, ExportSource::All, format),
+        Ok(_) => {}
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    }
+
     match get_all_transactions_from_db(&pool).await {
-        Ok(transactions) => {
-            let response = serde_json::json!({ "transactions": transactions });
-            HttpResponse::Ok().json(response)
-        }
+        Ok(transactions) => uncached_transactions_response(&req, transactions),
         Err(e) => {
-            eprintln!("Failed to fetch transactions: {}", e);
+            error!(error = %e, "Failed to fetch transactions");
             HttpResponse::InternalServerError().body("Failed to fetch transactions")
         }
     }
 }
 
 #[get("/lime/eth/{rlphex}")]
-pub async fn lime_eth_rlphex(path: web::Path<String>, pool: web::Data<DBPool>, req: HttpRequest) -> impl Responder {
-    let user = get_user_from_token(&req).await;
+pub async fn lime_eth_rlphex(path: web::Path<String>, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, csv_prices: web::Data<CsvPriceSource>, req: HttpRequest) -> impl Responder {
+    let user = match get_user_from_token(&req).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::from_error(e),
+    };
 
     let rlp_hex = path.into_inner();
     let hashes = match decode_rlp_hex(&rlp_hex) {
         Ok(hashes) => hashes,
-        Err(DecodeError::InvalidHex) => return HttpResponse::BadRequest().body("Invalid Hex String"),
-        Err(DecodeError::InvalidHashLen) => return HttpResponse::BadRequest().body("Invalid Hash Length"),
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
     };
 
-    let mut transactions: Vec<Transaction> = Vec::new();
-    for hash in hashes {
-        let mut transaction_found = false;
-        let tx_hash_str = format!("{:?}", hash);
-        
-        if let Ok(Some(tx)) = get_transaction_from_db(&pool, &tx_hash_str).await {
-            transaction_found = true;
-            transactions.push(tx);
+    let transactions = lookup_transactions(&pool, &config, &provider, &cache, &hashes, refresh_requested(&req), user.as_deref()).await;
+    transactions_response(&req, &pool, &config, &csv_prices, transactions).await
+}
+
+/// Same lookup as `/lime/eth` and `/lime/eth/{rlphex}`, but with the hashes in the body
+/// so large batches don't run into URL length limits. The body is either a JSON array
+/// of hashes, raw RLP bytes (`application/octet-stream`) or an RLP hex string.
+#[post("/lime/eth/batch")]
+pub async fn lime_eth_batch(body: web::Bytes, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, csv_prices: web::Data<CsvPriceSource>, req: HttpRequest) -> impl Responder {
+    let user = match get_user_from_token(&req).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+
+    let content_type = req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let hashes = if content_type.starts_with("application/json") {
+        let hash_strs: Vec<String> = match serde_json::from_slice(&body) {
+            Ok(hash_strs) => hash_strs,
+            Err(_) => return HttpResponse::BadRequest().body("Expected a JSON array of transaction hashes"),
+        };
+
+        match parse_transaction_hashes(&hash_strs) {
+            Some(hashes) => hashes,
+            None => return HttpResponse::BadRequest().body("Invalid Transaction Hash provided!"),
+        }
+    } else {
+        let decoded = if content_type.starts_with("application/octet-stream") {
+            decode_rlp_bytes(&body)
         } else {
-            if let Some(tx) = fetch_transaction(hash).await {
-                if let Err(e) = store_transaction_in_db(pool.clone(), &tx).await {
-                    // Shouldn't happen!!
-                    eprintln!("Failed to save transaction: {}", e);
-                }
-                transaction_found = true;
-                transactions.push(tx);
+            match std::str::from_utf8(&body) {
+                Ok(rlp_hex) => decode_rlp_hex(rlp_hex),
+                Err(e) => Err(DecodeError::InvalidHex { offset: e.valid_up_to() }),
             }
-        }
+        };
 
-        if transaction_found {
-            if let Some(ref user) = user {
-                if let Err(e) = store_user_search(pool.clone(), user, &tx_hash_str).await {
-                    eprintln!("Failed to save user search: {}", e);
-                }
-            }
+        match decoded {
+            Ok(hashes) => hashes,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
         }
+    };
 
+    let max_batch_size = config.server.max_batch_size;
+    if hashes.len() > max_batch_size {
+        return HttpResponse::PayloadTooLarge().body(format!("Batch size exceeds the maximum of {} hashes", max_batch_size));
     }
 
-    let response = serde_json::json!({ "transactions": transactions });
+    let transactions = lookup_transactions(&pool, &config, &provider, &cache, &hashes, refresh_requested(&req), user.as_deref()).await;
+    transactions_response(&req, &pool, &config, &csv_prices, transactions).await
+}
+
+/// Decodes a signed raw transaction (legacy or typed envelope) without broadcasting it.
+/// The body is either raw bytes (`application/octet-stream`) or a hex string.
+#[post("/lime/eth/decode")]
+pub async fn lime_eth_decode(body: web::Bytes, pool: web::Data<DBPool>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, req: HttpRequest) -> impl Responder {
+    let is_octet_stream = req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/octet-stream"));
+
+    let decoded = if is_octet_stream {
+        decode_raw_transaction(&body)
+    } else {
+        match std::str::from_utf8(&body) {
+            Ok(raw_hex) => decode_raw_transaction_hex(raw_hex),
+            Err(_) => return HttpResponse::BadRequest().body("Invalid Hex String"),
+        }
+    };
+
+    let raw_transaction = match decoded {
+        Ok(raw_transaction) => raw_transaction,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let cached = cache.get(&raw_transaction.transaction_hash).is_some() || match get_transaction_from_db(&pool, &raw_transaction.transaction_hash).await {
+        Ok(stored) => stored.is_some(),
+        Err(e) => {
+            error!(error = %e, "Failed to fetch transaction");
+            return HttpResponse::InternalServerError().body("Failed to fetch transaction");
+        }
+    };
+    let on_chain = cached || match H256::from_str(&raw_transaction.transaction_hash) {
+        Ok(tx_hash) => match transaction_exists_on_chain(&provider, tx_hash).await {
+            Ok(exists) => exists,
+            Err(e) => {
+                error!(error = %e, "Failed to look the transaction up on chain");
+                return HttpResponse::BadGateway().body("Failed to look the transaction up on chain");
+            }
+        },
+        Err(_) => false,
+    };
+
+    let response = serde_json::json!({
+        "transaction": raw_transaction,
+        "cached": cached,
+        "onChain": on_chain,
+    });
     HttpResponse::Ok().json(response)
 }
 
 
 #[get("/lime/my")]
 pub async fn lime_my(req: HttpRequest, pool: web::Data<DBPool>) -> impl Responder {
-    if let Some(username) = get_user_from_token(&req).await {
-        match get_user_search_transactions(&pool, &username).await {
-            Ok(transactions) => {
-                let response = serde_json::json!({ "transactions": transactions });
-                HttpResponse::Ok().json(response)
+    let user = match get_user_from_token(&req).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+    if let Some(username) = user {
+        match OutputFormat::from_request(&req) {
+            Ok(format @ (OutputFormat::Csv | OutputFormat::Ndjson)) => {
+                return export_response(&req, &pool, ExportSource::UserSearches(username), format);
             }
+            Ok(_) => {}
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        }
+        match get_user_search_transactions(&pool, &username).await {
+            Ok(transactions) => uncached_transactions_response(&req, transactions),
             Err(e) => {
-                eprintln!("Failed to fetch user transactions: {}", e);
+                error!(error = %e, "Failed to fetch user transactions");
                 HttpResponse::InternalServerError().body("Failed to fetch user transactions")
             }
         }
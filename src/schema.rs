@@ -1,5 +1,95 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    blocks (hash) {
+        hash -> Text,
+        number -> Int8,
+        parent_hash -> Text,
+        timestamp -> Int8,
+        miner -> Text,
+        gas_used -> Int8,
+        gas_limit -> Int8,
+        base_fee_per_gas -> Nullable<Text>,
+        transaction_count -> Int8,
+    }
+}
+
+diesel::table! {
+    contract_abis (address) {
+        address -> Text,
+        abi -> Text,
+    }
+}
+
+diesel::table! {
+    eth_prices (currency, day) {
+        currency -> Text,
+        day -> Text,
+        price -> Text,
+    }
+}
+
+diesel::table! {
+    token_metadata (token) {
+        token -> Text,
+        name -> Nullable<Text>,
+        symbol -> Nullable<Text>,
+        decimals -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    token_transfers (transaction_hash, log_index) {
+        transaction_hash -> Text,
+        log_index -> Int8,
+        block_number -> Int8,
+        token -> Text,
+        from_address -> Text,
+        to_address -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    transaction_fees (transaction_hash) {
+        transaction_hash -> Text,
+        gas_used -> Int8,
+        effective_gas_price -> Text,
+        fee -> Text,
+    }
+}
+
+diesel::table! {
+    transaction_logs (transaction_hash, log_index) {
+        transaction_hash -> Text,
+        log_index -> Int8,
+        block_number -> Int8,
+        address -> Text,
+        topic0 -> Nullable<Text>,
+        topic1 -> Nullable<Text>,
+        topic2 -> Nullable<Text>,
+        topic3 -> Nullable<Text>,
+        data -> Text,
+    }
+}
+
+diesel::table! {
+    transaction_traces (transaction_hash, trace_address) {
+        transaction_hash -> Text,
+        trace_address -> Text,
+        depth -> Int4,
+        call_type -> Text,
+        from_address -> Text,
+        to_address -> Nullable<Text>,
+        value -> Text,
+        gas -> Int8,
+        gas_used -> Int8,
+        input -> Text,
+        output -> Nullable<Text>,
+        error -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     transactions (transaction_hash) {
         transaction_hash -> Text,
@@ -15,6 +105,20 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    unknown_transactions (transaction_hash) {
+        transaction_hash -> Text,
+        checked_at -> Int8,
+    }
+}
+
+diesel::table! {
+    users (username) {
+        username -> Text,
+        password_hash -> Text,
+    }
+}
+
 diesel::table! {
     users_searches (id) {
         id -> Int4,
@@ -23,9 +127,22 @@ diesel::table! {
     }
 }
 
-diesel::joinable!(users_searches -> transactions (transaction_hash));
+diesel::joinable!(token_transfers -> transactions (transaction_hash));
+diesel::joinable!(transaction_fees -> transactions (transaction_hash));
+diesel::joinable!(transaction_logs -> transactions (transaction_hash));
+diesel::joinable!(transaction_traces -> transactions (transaction_hash));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    blocks,
+    contract_abis,
+    eth_prices,
+    token_metadata,
+    token_transfers,
+    transaction_fees,
+    transaction_logs,
+    transaction_traces,
     transactions,
+    unknown_transactions,
+    users,
     users_searches,
 );
@@ -1,8 +1,277 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
 use diesel_migrations::{EmbeddedMigrations, embed_migrations, MigrationHarness};
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
 use diesel::PgConnection;
+use tracing::{info, warn};
+
+use crate::DBPool;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-pub fn run_migrations(conn: &mut PgConnection) {
-    conn.run_pending_migrations(MIGRATIONS).unwrap();
+/// How long a single connection attempt waits for Postgres
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Upper bound of the wait between two connection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How often the database is checked while it is up, in degraded mode
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Routes that keep working without the database, `/lime/eth` skipping the cache
+const DEGRADED_ROUTES: [&str; 4] = ["/lime/eth", "/healthz", "/readyz", "/metrics"];
+
+/// Applies the pending migrations, returning their versions
+pub fn run_migrations(conn: &mut PgConnection) -> Result<Vec<String>, String> {
+    conn.run_pending_migrations(MIGRATIONS)
+        .map(|versions| versions.iter().map(ToString::to_string).collect())
+        .map_err(|e| e.to_string())
+}
+
+/// Reverts the most recently applied migration, returning its version
+pub fn revert_last_migration(conn: &mut PgConnection) -> Result<String, String> {
+    conn.revert_last_migration(MIGRATIONS)
+        .map(|version| version.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Every embedded migration by name, with whether it was applied
+pub fn migration_status(conn: &mut PgConnection) -> Result<Vec<(String, bool)>, String> {
+    let applied: HashSet<String> = conn.applied_migrations()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let migrations = MigrationSource::<Pg>::migrations(&MIGRATIONS).map_err(|e| e.to_string())?;
+    Ok(migrations.iter()
+        .map(|migration| {
+            let name = migration.name();
+            (name.to_string(), applied.contains(&name.version().to_string()))
+        })
+        .collect())
+}
+
+/// Number of embedded migrations not yet applied to the database
+pub fn pending_migrations(conn: &mut PgConnection) -> Result<usize, String> {
+    conn.pending_migrations(MIGRATIONS)
+        .map(|migrations| migrations.len())
+        .map_err(|e| e.to_string())
+}
+
+/// Wait before retrying after `attempt` failed ones: `initial`, doubled each time, capped at `MAX_BACKOFF`
+pub fn backoff_delay(initial: Duration, attempt: u32) -> Duration {
+    initial.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(MAX_BACKOFF)
+}
+
+/// Checks that a connection can be checked out
+fn connect(pool: &DBPool) -> Result<(), String> {
+    pool.get_timeout(CONNECT_TIMEOUT).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Checks out a connection and applies the pending migrations with it
+fn connect_and_migrate(pool: &DBPool) -> Result<(), String> {
+    let mut conn = pool.get_timeout(CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+    run_migrations(&mut conn).map(|_| ())
+}
+
+/// Connects to the database and, if `migrate`, migrates it. Retries `retries` times
+/// with an exponential backoff so the server can start before Postgres is ready.
+pub async fn wait_for_database(pool: &DBPool, retries: u32, backoff: Duration, migrate: bool) -> Result<(), String> {
+    let mut attempt = 0;
+    loop {
+        let pool = pool.clone();
+        let result = web::block(move || match migrate {
+            true => connect_and_migrate(&pool),
+            false => connect(&pool),
+        }).await.map_err(|e| e.to_string())?;
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= retries => return Err(e),
+            Err(e) => {
+                attempt += 1;
+                let delay = backoff_delay(backoff, attempt);
+                warn!(error = %e, attempt, retries, delay_ms = delay.as_millis() as u64, "Database unavailable, retrying");
+                actix_web::rt::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Whether the database can currently be used, shared between the workers in degraded mode
+#[derive(Debug, Clone)]
+pub struct DatabaseStatus(Arc<AtomicBool>);
+
+impl DatabaseStatus {
+    pub fn new(available: bool) -> Self {
+        DatabaseStatus(Arc::new(AtomicBool::new(available)))
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set_available(&self, available: bool) {
+        self.0.store(available, Ordering::Relaxed);
+    }
+}
+
+/// Keeps `status` up to date for the lifetime of the server, backing off
+/// while the database is down and migrating it whenever it comes back
+pub async fn monitor_database(pool: DBPool, status: DatabaseStatus, backoff: Duration) {
+    let mut failures = 0;
+    loop {
+        let delay = match failures {
+            0 => MONITOR_INTERVAL,
+            failures => backoff_delay(backoff, failures),
+        };
+        actix_web::rt::time::sleep(delay).await;
+
+        let check_pool = pool.clone();
+        let was_available = status.is_available();
+        let result = web::block(move || match was_available {
+            true => connect(&check_pool),
+            false => connect_and_migrate(&check_pool),
+        }).await.unwrap_or_else(|e| Err(e.to_string()));
+
+        match result {
+            Ok(()) => {
+                if !was_available {
+                    info!("Database is back, leaving degraded mode");
+                }
+                failures = 0;
+                status.set_available(true);
+            }
+            Err(e) => {
+                if was_available {
+                    warn!(error = %e, "Database unavailable, entering degraded mode");
+                }
+                failures += 1;
+                status.set_available(false);
+            }
+        }
+    }
+}
+
+/// Available unless degraded mode is on and the database is down
+pub fn database_available(req: &HttpRequest) -> bool {
+    req.app_data::<web::Data<DatabaseStatus>>().is_none_or(|status| status.is_available())
+}
+
+/// Answers `503` for the routes that need the database while it is down
+pub async fn require_database(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if !database_available(req.request()) && !DEGRADED_ROUTES.contains(&req.path()) {
+        let response = HttpResponse::ServiceUnavailable().body("Database unavailable");
+        return Ok(req.into_response(response));
+    }
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, test, App, Responder};
+    use actix_web::middleware::from_fn;
+
+    #[actix_web::test]
+    async fn test_backoff_delay() {
+        let initial = Duration::from_millis(500);
+        assert_eq!(backoff_delay(initial, 1), Duration::from_millis(500));
+        assert_eq!(backoff_delay(initial, 2), Duration::from_secs(1));
+        assert_eq!(backoff_delay(initial, 4), Duration::from_secs(4));
+        assert_eq!(backoff_delay(initial, 40), MAX_BACKOFF);
+    }
+
+    #[get("/lime/all")]
+    async fn all() -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    #[get("/lime/eth")]
+    async fn eth() -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_require_database() {
+        let status = DatabaseStatus::new(false);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(status.clone()))
+                .wrap(from_fn(require_database))
+                .service(all)
+                .service(eth)
+        ).await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/lime/all").to_request()).await;
+        assert_eq!(resp.status(), 503);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/lime/eth?transactionHashes=0x01").to_request()).await;
+        assert_eq!(resp.status(), 200);
+
+        status.set_available(true);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/lime/all").to_request()).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    /// Node answering a single `eth_chainId` request with chain 1
+    fn stub_node() -> String {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request);
+            let body = r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#;
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            let _ = stream.write_all(response.as_bytes());
+        });
+        url
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_degraded() {
+        use diesel::r2d2::{ConnectionManager, Pool};
+        use crate::config::Config;
+        use crate::health::readyz;
+
+        let unreachable: DBPool = Pool::builder()
+            .connection_timeout(Duration::from_millis(100))
+            .build_unchecked(ConnectionManager::new("postgresql://nobody@127.0.0.1:1/nothing"));
+        let status = DatabaseStatus::new(false);
+
+        for (degraded_mode, node_url, expected) in [
+            (true, stub_node(), 200),
+            (false, stub_node(), 503),
+            (true, "http://127.0.0.1:1".to_string(), 503),
+        ] {
+            let mut config = Config::default();
+            config.database.degraded_mode = degraded_mode;
+            config.eth.node_url = node_url;
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(status.clone()))
+                    .app_data(web::Data::new(unreachable.clone()))
+                    .app_data(web::Data::new(config.eth_provider().unwrap()))
+                    .app_data(web::Data::new(config))
+                    .wrap(from_fn(require_database))
+                    .service(readyz)
+            ).await;
+
+            let resp = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+            assert_eq!(resp.status(), expected, "degraded_mode: {}", degraded_mode);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            if expected == 200 {
+                assert_eq!(body["status"], "degraded");
+                assert_eq!(body["checks"]["database"]["status"], "degraded");
+                assert_eq!(body["checks"]["ethNode"]["status"], "ok");
+            }
+        }
+    }
 }
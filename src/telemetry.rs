@@ -0,0 +1,196 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use diesel::connection::{Instrumentation, InstrumentationEvent};
+use tracing::{Instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Installs the global subscriber. Logs go to stderr, as JSON when
+/// `LOG_FORMAT=json`, filtered by `RUST_LOG` (`info` by default). With the
+/// `otlp` feature and `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also
+/// exported to that OpenTelemetry collector.
+pub fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => fmt::layer().json().with_current_span(true).with_writer(std::io::stderr).boxed(),
+        _ => fmt::layer().with_writer(std::io::stderr).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otlp_layer())
+        .init();
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>() -> Option<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .build()
+        .map_err(|e| eprintln!("Failed to create OTLP exporter: {}", e))
+        .ok()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]))
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    opentelemetry::global::set_tracer_provider(provider);
+
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(not(feature = "otlp"))]
+fn otlp_layer() -> Option<tracing_subscriber::layer::Identity> {
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_ok() {
+        eprintln!("OTEL_EXPORTER_OTLP_ENDPOINT is set but the server was built without the `otlp` feature");
+    }
+    None
+}
+
+/// The caller's `X-Request-Id` if it looks like an id, a new UUID otherwise
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Runs every request in a span carrying its request id, so logs of the
+/// DB queries and RPC calls it makes can be correlated, and echoes the id
+/// back in the `X-Request-Id` response header
+pub async fn trace_requests(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = request_id(&req);
+    let span = tracing::info_span!(
+        "http_request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+        status = tracing::field::Empty,
+    );
+    let started = Instant::now();
+
+    let mut result = next.call(req).instrument(span.clone()).await;
+
+    let _entered = span.enter();
+    match &mut result {
+        Ok(res) => {
+            span.record("status", res.status().as_u16());
+            tracing::info!(status = res.status().as_u16(), elapsed_ms = started.elapsed().as_millis() as u64, "request completed");
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+        }
+        Err(e) => tracing::error!(error = %e, elapsed_ms = started.elapsed().as_millis() as u64, "request failed"),
+    }
+
+    result
+}
+
+/// Diesel instrumentation opening a `db_query` span for every statement,
+/// nested in the span of the request that ran it
+#[derive(Default)]
+pub struct QueryTracing {
+    query: Option<(Span, Instant)>,
+}
+
+impl Instrumentation for QueryTracing {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { query, .. } => {
+                let span = tracing::info_span!("db_query", statement = statement_sql(&query.to_string()));
+                self.query = Some((span, Instant::now()));
+            }
+            InstrumentationEvent::FinishQuery { error, .. } => {
+                let Some((span, started)) = self.query.take() else {
+                    return;
+                };
+                let _entered = span.enter();
+                let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+                match error {
+                    Some(e) => tracing::warn!(error = %e, elapsed_ms, "query failed"),
+                    None => tracing::debug!(elapsed_ms, "query finished"),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The SQL text of a rendered statement, without the bind values diesel
+/// appends (password hashes, usernames, ...)
+fn statement_sql(rendered: &str) -> &str {
+    rendered.split_once(" -- binds: ").map_or(rendered, |(sql, _)| sql)
+}
+
+/// Traces the queries of every connection diesel establishes from now on
+pub fn trace_queries() {
+    diesel::connection::set_default_instrumentation(|| Some(Box::new(QueryTracing::default())))
+        .expect("Failed to set up query tracing");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, test, App, HttpResponse, Responder};
+    use actix_web::middleware::from_fn;
+
+    #[get("/test/request-id")]
+    async fn ok() -> impl Responder {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_header() {
+        let app = test::init_service(App::new().wrap(from_fn(trace_requests)).service(ok)).await;
+
+        let req = test::TestRequest::get().uri("/test/request-id").to_request();
+        let resp = test::call_service(&app, req).await;
+        let generated = resp.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+
+        let req = test::TestRequest::get()
+            .uri("/test/request-id")
+            .insert_header((REQUEST_ID_HEADER, "client-42"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "client-42");
+
+        let req = test::TestRequest::get()
+            .uri("/test/request-id")
+            .insert_header((REQUEST_ID_HEADER, "has spaces"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "has spaces");
+    }
+
+    #[actix_web::test]
+    async fn test_statement_sql_drops_binds() {
+        use crate::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        let query = users.filter(username.eq("alice")).select(password_hash);
+        let rendered = diesel::debug_query::<diesel::pg::Pg, _>(&query).to_string();
+        assert!(rendered.contains("alice"));
+        let sql = statement_sql(&rendered);
+        assert!(sql.starts_with("SELECT"));
+        assert!(!sql.contains("alice"));
+    }
+}
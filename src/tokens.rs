@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use actix_web::{web, get, Responder, HttpResponse};
+use serde::{Deserialize, Serialize};
+use diesel::prelude::*;
+
+use ethers::abi::{decode, ParamType, Token};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use tracing::{error, warn};
+
+use crate::DBPool;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::events::{TransactionLog, decode_log};
+use crate::schema::{token_metadata, token_transfers};
+use crate::transaction::EthProvider;
+
+const NAME_SELECTOR: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+const SYMBOL_SELECTOR: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+/// Default and largest page of `/lime/address/{address}/tokens`, in tokens.
+/// Each token seen for the first time costs up to three `eth_call`s.
+const DEFAULT_TOKENS_LIMIT: i64 = 20;
+const MAX_TOKENS_LIMIT: i64 = 100;
+
+/// ERC-20 `Transfer` extracted from a transaction log
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[diesel(table_name = token_transfers)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenTransfer {
+    pub transaction_hash: String,
+    pub log_index: i64,
+    pub block_number: i64,
+    pub token: String,
+    #[serde(rename = "from")]
+    pub from_address: String,
+    #[serde(rename = "to")]
+    pub to_address: String,
+    pub value: String,
+}
+
+/// Token details read once from the contract and cached. Fields the contract
+/// doesn't implement stay empty.
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[diesel(table_name = token_metadata)]
+pub struct TokenMetadata {
+    pub token: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<i32>,
+}
+
+impl TokenMetadata {
+    fn unknown(token: &str) -> Self {
+        TokenMetadata { token: token.to_string(), name: None, symbol: None, decimals: None }
+    }
+}
+
+/// ERC-20 transfers among the given logs, ERC-721 transfers share the event
+/// signature but carry the token id as an extra topic and are skipped
+pub fn token_transfers_from_logs(logs: &[TransactionLog]) -> Vec<TokenTransfer> {
+    logs.iter()
+        .filter_map(|log| decode_log(None, log))
+        .filter(|event| event.standard == Some("ERC-20") && event.event == "Transfer")
+        .filter_map(|event| {
+            let argument = |index: usize| event.arguments.get(index).and_then(|arg| arg.value.as_str()).map(str::to_string);
+            Some(TokenTransfer {
+                transaction_hash: event.transaction_hash.clone(),
+                log_index: event.log_index,
+                block_number: event.block_number,
+                token: event.address.clone(),
+                from_address: argument(0)?,
+                to_address: argument(1)?,
+                value: argument(2)?,
+            })
+        })
+        .collect()
+}
+
+pub fn insert_token_transfers(conn: &mut PgConnection, transfers: &[TokenTransfer]) -> Result<usize, diesel::result::Error> {
+    use crate::schema::token_transfers::dsl::*;
+
+    let mut inserted = 0;
+    for chunk in transfers.chunks(MAX_BIND_PARAMS / 7) {
+        inserted += diesel::insert_into(token_transfers)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(inserted)
+}
+
+/// Only needed to seed tests, lookups store transfers along with their transaction
+#[cfg(test)]
+pub async fn store_token_transfers_in_db(pool: &DBPool, transfers: &[TokenTransfer]) -> Result<(), diesel::result::Error> {
+    let transfers = transfers.to_vec();
+    with_conn(pool, move |conn| insert_token_transfers(conn, &transfers).map(|_| ())).await
+}
+
+/// Up to `limit` tokens `address` sent or received, ordered by token address
+/// and starting after `after`
+pub async fn get_tokens_for_address(pool: &DBPool, address: &str, after: Option<&str>, limit: i64) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::token_transfers::dsl::*;
+
+    let address = address.to_string();
+    let after = after.unwrap_or_default().to_string();
+    with_conn(pool, move |conn| {
+        token_transfers
+            .filter(from_address.eq(address.clone()).or(to_address.eq(address)))
+            .filter(token.gt(after))
+            .select(token)
+            .distinct()
+            .order(token)
+            .limit(limit)
+            .load::<String>(conn)
+    }).await
+}
+
+/// Transfers of the given tokens `address` sent or received
+pub async fn get_token_transfers_for_address(pool: &DBPool, address: &str, tokens: &[String]) -> Result<Vec<TokenTransfer>, diesel::result::Error> {
+    use crate::schema::token_transfers::dsl::*;
+
+    let address = address.to_string();
+    let tokens = tokens.to_vec();
+    with_conn(pool, move |conn| {
+        token_transfers
+            .filter(from_address.eq(address.clone()).or(to_address.eq(address)))
+            .filter(token.eq_any(tokens))
+            .order((block_number, log_index))
+            .load::<TokenTransfer>(conn)
+    }).await
+}
+
+async fn get_token_metadata_from_db(pool: &DBPool, tokens: &[String]) -> Result<Vec<TokenMetadata>, diesel::result::Error> {
+    use crate::schema::token_metadata::dsl::*;
+
+    let tokens = tokens.to_vec();
+    with_conn(pool, move |conn| token_metadata.filter(token.eq_any(tokens)).load::<TokenMetadata>(conn)).await
+}
+
+async fn store_token_metadata_in_db(pool: &DBPool, metadata: &TokenMetadata) -> Result<(), diesel::result::Error> {
+    use crate::schema::token_metadata::dsl::*;
+
+    let metadata = metadata.clone();
+    with_conn(pool, move |conn| {
+        diesel::insert_into(token_metadata)
+            .values(&metadata)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }).await
+}
+
+/// `None` when the node answers with an error, usually a revert of a token
+/// that doesn't implement the function. Failing to reach the node is an `Err`.
+async fn call_token(provider: &EthProvider, token: Address, selector: [u8; 4]) -> Result<Option<Bytes>, ProviderError> {
+    let call: TypedTransaction = TransactionRequest::new().to(token).data(selector.to_vec()).into();
+    match provider.call(&call, None).await {
+        Ok(output) => Ok(Some(output)),
+        Err(e) if RpcError::is_error_response(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// `name()` / `symbol()` return a string, some older tokens return `bytes32` instead
+fn decode_string_output(output: &[u8]) -> Option<String> {
+    if let Ok(tokens) = decode(&[ParamType::String], output) {
+        if let Some(Token::String(value)) = tokens.into_iter().next() {
+            return Some(value);
+        }
+    }
+    if output.len() == 32 {
+        let trimmed: Vec<u8> = output.iter().copied().take_while(|byte| *byte != 0).collect();
+        return String::from_utf8(trimmed).ok().filter(|value| !value.is_empty());
+    }
+    None
+}
+
+/// Reads name, symbol and decimals of an ERC-20 contract through `eth_call`
+pub async fn fetch_token_metadata(provider: &EthProvider, token: &str) -> Result<TokenMetadata, ProviderError> {
+    let mut metadata = TokenMetadata::unknown(token);
+    let Ok(address) = Address::from_str(token) else {
+        return Ok(metadata);
+    };
+
+    if let Some(output) = call_token(provider, address, NAME_SELECTOR).await? {
+        metadata.name = decode_string_output(&output);
+    }
+    if let Some(output) = call_token(provider, address, SYMBOL_SELECTOR).await? {
+        metadata.symbol = decode_string_output(&output);
+    }
+    if let Some(output) = call_token(provider, address, DECIMALS_SELECTOR).await? {
+        if let Ok(tokens) = decode(&[ParamType::Uint(8)], &output) {
+            metadata.decimals = tokens.into_iter().next().and_then(|token| token.into_uint()).map(|decimals| decimals.low_u32() as i32);
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Metadata of the given tokens, fetching and caching the ones seen for the first time.
+/// Tokens the node couldn't be asked about go without metadata and are retried next time.
+pub async fn get_token_metadata(pool: &DBPool, provider: &EthProvider, tokens: &[String]) -> Result<Vec<TokenMetadata>, diesel::result::Error> {
+    let mut metadata = get_token_metadata_from_db(pool, tokens).await?;
+
+    for token in tokens {
+        if metadata.iter().any(|cached| &cached.token == token) {
+            continue;
+        }
+        match fetch_token_metadata(provider, token).await {
+            Ok(fetched) => {
+                if let Err(e) = store_token_metadata_in_db(pool, &fetched).await {
+                    error!(error = %e, "Failed to save token metadata");
+                }
+                metadata.push(fetched);
+            }
+            Err(e) => {
+                warn!(error = %e, token, "Failed to fetch token metadata");
+                metadata.push(TokenMetadata::unknown(token));
+            }
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Token transfers of one address for one token, with the net flow in raw token units
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenHistory {
+    pub token: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<i32>,
+    pub received: String,
+    pub sent: String,
+    pub net: String,
+    pub transfers: Vec<TokenTransfer>,
+}
+
+/// Groups transfers by token and sums what `address` received and sent
+pub fn token_histories(address: &str, transfers: Vec<TokenTransfer>, metadata: &[TokenMetadata]) -> Vec<TokenHistory> {
+    let mut by_token: BTreeMap<String, Vec<TokenTransfer>> = BTreeMap::new();
+    for transfer in transfers {
+        by_token.entry(transfer.token.clone()).or_default().push(transfer);
+    }
+
+    by_token
+        .into_iter()
+        .map(|(token, transfers)| {
+            let mut received = U256::zero();
+            let mut sent = U256::zero();
+            for transfer in &transfers {
+                let value = U256::from_dec_str(&transfer.value).unwrap_or_default();
+                if transfer.to_address == address {
+                    received = received.saturating_add(value);
+                }
+                if transfer.from_address == address {
+                    sent = sent.saturating_add(value);
+                }
+            }
+            let net = if received >= sent {
+                (received - sent).to_string()
+            } else {
+                format!("-{}", sent - received)
+            };
+
+            let metadata = metadata.iter().find(|metadata| metadata.token == token);
+            TokenHistory {
+                name: metadata.and_then(|metadata| metadata.name.clone()),
+                symbol: metadata.and_then(|metadata| metadata.symbol.clone()),
+                decimals: metadata.and_then(|metadata| metadata.decimals),
+                token,
+                received: received.to_string(),
+                sent: sent.to_string(),
+                net,
+                transfers,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokensQuery {
+    /// Page size in tokens, `DEFAULT_TOKENS_LIMIT` unless set and at most `MAX_TOKENS_LIMIT`
+    pub limit: Option<i64>,
+    /// `nextCursor` of the previous page, the last token it held
+    pub after: Option<String>,
+}
+
+#[get("/lime/address/{address}/tokens")]
+pub async fn lime_address_tokens(path: web::Path<String>, query: web::Query<TokensQuery>, pool: web::Data<DBPool>, provider: web::Data<EthProvider>) -> impl Responder {
+    let Ok(address) = Address::from_str(&path.into_inner()).map(|address| format!("{:?}", address)) else {
+        return HttpResponse::BadRequest().body("Invalid Address");
+    };
+    let TokensQuery { limit, after } = query.into_inner();
+    let limit = match limit {
+        None => DEFAULT_TOKENS_LIMIT,
+        Some(limit) if limit > 0 => limit.min(MAX_TOKENS_LIMIT),
+        Some(_) => return HttpResponse::BadRequest().body("Invalid Limit"),
+    };
+    let after = match after.as_deref().map(Address::from_str).transpose() {
+        Ok(after) => after.map(|after| format!("{:?}", after)),
+        Err(_) => return HttpResponse::BadRequest().body("Invalid Cursor"),
+    };
+
+    // One more than the page to tell whether another one follows
+    let mut tokens = match get_tokens_for_address(&pool, &address, after.as_deref(), limit + 1).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch tokens");
+            return HttpResponse::InternalServerError().body("Failed to fetch token transfers");
+        }
+    };
+    let next_cursor = if tokens.len() as i64 > limit {
+        tokens.truncate(limit as usize);
+        tokens.last().cloned()
+    } else {
+        None
+    };
+
+    let transfers = match get_token_transfers_for_address(&pool, &address, &tokens).await {
+        Ok(transfers) => transfers,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch token transfers");
+            return HttpResponse::InternalServerError().body("Failed to fetch token transfers");
+        }
+    };
+
+    let metadata = match get_token_metadata(&pool, &provider, &tokens).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch token metadata");
+            Vec::new()
+        }
+    };
+
+    let response = serde_json::json!({
+        "address": address,
+        "tokens": token_histories(&address, transfers, &metadata),
+        "nextCursor": next_cursor,
+    });
+    HttpResponse::Ok().json(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "0x1111111111111111111111111111111111111111";
+    const OTHER: &str = "0x2222222222222222222222222222222222222222";
+    const TOKEN: &str = "0x3333333333333333333333333333333333333333";
+
+    fn transfer(log_index: i64, from: &str, to: &str, value: &str) -> TokenTransfer {
+        TokenTransfer {
+            transaction_hash: format!("{:?}", H256::repeat_byte(0x01)),
+            log_index,
+            block_number: 1,
+            token: TOKEN.to_string(),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_transfers_from_logs() {
+        let log = TransactionLog {
+            transaction_hash: format!("{:?}", H256::repeat_byte(0x01)),
+            log_index: 3,
+            block_number: 10,
+            address: TOKEN.to_string(),
+            topic0: Some("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef".to_string()),
+            topic1: Some(format!("0x000000000000000000000000{}", &OWNER[2..])),
+            topic2: Some(format!("0x000000000000000000000000{}", &OTHER[2..])),
+            topic3: None,
+            data: "0x00000000000000000000000000000000000000000000000000000000000003e8".to_string(),
+        };
+        // Same event with the value as a topic is an ERC-721 transfer
+        let nft_log = TransactionLog {
+            topic3: Some(format!("{:?}", H256::from_low_u64_be(7))),
+            data: "0x".to_string(),
+            ..log.clone()
+        };
+
+        let transfers = token_transfers_from_logs(&[log, nft_log]);
+        assert_eq!(transfers, vec![TokenTransfer { block_number: 10, ..transfer(3, OWNER, OTHER, "1000") }]);
+    }
+
+    #[test]
+    fn test_token_histories_net_flows() {
+        let transfers = vec![
+            transfer(0, OTHER, OWNER, "1000"),
+            transfer(1, OWNER, OTHER, "1500"),
+        ];
+        let metadata = vec![TokenMetadata { token: TOKEN.to_string(), name: None, symbol: Some("TKN".to_string()), decimals: Some(18) }];
+
+        let histories = token_histories(OWNER, transfers, &metadata);
+        assert_eq!(histories.len(), 1);
+        assert_eq!(histories[0].symbol.as_deref(), Some("TKN"));
+        assert_eq!(histories[0].received, "1000");
+        assert_eq!(histories[0].sent, "1500");
+        assert_eq!(histories[0].net, "-500");
+        assert_eq!(histories[0].transfers.len(), 2);
+    }
+
+    #[test]
+    fn test_decode_string_output() {
+        let encoded = ethers::abi::encode(&[Token::String("Token".to_string())]);
+        assert_eq!(decode_string_output(&encoded).as_deref(), Some("Token"));
+
+        let mut bytes32 = b"MKR".to_vec();
+        bytes32.resize(32, 0);
+        assert_eq!(decode_string_output(&bytes32).as_deref(), Some("MKR"));
+
+        assert_eq!(decode_string_output(&[]), None);
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_web::{web, get, HttpRequest, Responder, HttpResponse};
+use serde::{Deserialize, Serialize};
+use diesel::prelude::*;
+
+use ethers::prelude::*;
+use ethers::types::{
+    CallFrame,
+    GethDebugBuiltInTracerType,
+    GethDebugTracerType,
+    GethDebugTracingOptions,
+    Action,
+    Res,
+    Trace,
+};
+use tracing::error;
+
+use crate::DBPool;
+use crate::cache::TransactionCache;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::routes::{lookup_transaction, refresh_requested};
+use crate::schema::transaction_traces;
+use crate::config::Config;
+use crate::transaction::EthProvider;
+
+/// Which tracing API of the node to use, set through `eth.trace_mode`. Tracing
+/// is off unless configured, as most public nodes don't expose either API.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TraceMode {
+    /// Geth style `debug_traceTransaction` with the built-in `callTracer`
+    CallTracer,
+    /// OpenEthereum / Erigon style `trace_transaction`
+    Parity,
+}
+
+impl FromStr for TraceMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "callTracer" | "debug" => Ok(TraceMode::CallTracer),
+            "parity" | "trace" => Ok(TraceMode::Parity),
+            _ => Err(format!("unknown trace mode `{}`, expected `callTracer` or `parity`", mode)),
+        }
+    }
+}
+
+impl TryFrom<String> for TraceMode {
+    type Error = String;
+
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        mode.parse()
+    }
+}
+
+/// A single call of the call tree, `trace_address` locates it in the tree
+/// (`""` for the top-level call, `"0.1"` for the second call of the first one)
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Insertable)]
+#[diesel(table_name = transaction_traces)]
+#[serde(rename_all = "camelCase")]
+pub struct InternalCall {
+    #[serde(skip)]
+    pub transaction_hash: String,
+    pub trace_address: String,
+    pub depth: i32,
+    pub call_type: String,
+    #[serde(rename = "from")]
+    pub from_address: String,
+    #[serde(rename = "to")]
+    pub to_address: Option<String>,
+    pub value: String,
+    pub gas: i64,
+    pub gas_used: i64,
+    pub input: String,
+    pub output: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Call with its nested calls, as returned by `/lime/eth/{hash}/trace`
+#[derive(Debug, Serialize)]
+pub struct CallNode {
+    #[serde(flatten)]
+    pub call: InternalCall,
+    pub calls: Vec<CallNode>,
+}
+
+fn trace_address_string(trace_address: &[usize]) -> String {
+    trace_address.iter().map(usize::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Flattens a `callTracer` frame depth first, so parents come before their calls
+pub fn calls_from_call_frame(tx_hash: &str, frame: &CallFrame) -> Vec<InternalCall> {
+    fn visit(tx_hash: &str, frame: &CallFrame, trace_address: &mut Vec<usize>, calls: &mut Vec<InternalCall>) {
+        calls.push(InternalCall {
+            transaction_hash: tx_hash.to_string(),
+            trace_address: trace_address_string(trace_address),
+            depth: trace_address.len() as i32,
+            call_type: frame.typ.to_lowercase(),
+            from_address: format!("{:?}", frame.from),
+            to_address: frame.to.as_ref().and_then(|to| to.as_address()).map(|to| format!("{:?}", to)),
+            value: frame.value.unwrap_or_default().to_string(),
+            gas: frame.gas.low_u64() as i64,
+            gas_used: frame.gas_used.low_u64() as i64,
+            input: frame.input.to_string(),
+            output: frame.output.as_ref().map(Bytes::to_string),
+            error: frame.error.clone(),
+        });
+
+        for (index, call) in frame.calls.iter().flatten().enumerate() {
+            trace_address.push(index);
+            visit(tx_hash, call, trace_address, calls);
+            trace_address.pop();
+        }
+    }
+
+    let mut calls = Vec::new();
+    visit(tx_hash, frame, &mut Vec::new(), &mut calls);
+    calls
+}
+
+/// Converts the flat `trace_transaction` result, rewards aren't calls and are skipped
+pub fn calls_from_parity_traces(tx_hash: &str, traces: &[Trace]) -> Vec<InternalCall> {
+    traces
+        .iter()
+        .filter_map(|trace| {
+            let (call_type, from, to, value, gas, input) = match &trace.action {
+                Action::Call(call) => {
+                    let call_type = serde_json::to_value(&call.call_type).ok()?.as_str()?.to_string();
+                    (call_type, call.from, Some(call.to), call.value, call.gas, call.input.to_string())
+                }
+                Action::Create(create) => ("create".to_string(), create.from, None, create.value, create.gas, create.init.to_string()),
+                Action::Suicide(suicide) => ("selfdestruct".to_string(), suicide.address, Some(suicide.refund_address), suicide.balance, U256::zero(), "0x".to_string()),
+                Action::Reward(_) => return None,
+            };
+            let (to, gas_used, output) = match &trace.result {
+                Some(Res::Call(result)) => (to, result.gas_used, Some(result.output.to_string())),
+                Some(Res::Create(result)) => (Some(result.address), result.gas_used, Some(result.code.to_string())),
+                Some(Res::None) | None => (to, U256::zero(), None),
+            };
+
+            Some(InternalCall {
+                transaction_hash: tx_hash.to_string(),
+                trace_address: trace_address_string(&trace.trace_address),
+                depth: trace.trace_address.len() as i32,
+                call_type,
+                from_address: format!("{:?}", from),
+                to_address: to.map(|to| format!("{:?}", to)),
+                value: value.to_string(),
+                gas: gas.low_u64() as i64,
+                gas_used: gas_used.low_u64() as i64,
+                input,
+                output,
+                error: trace.error.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Rebuilds the tree out of the stored calls, grouping them by parent trace
+/// address once so that deep or wide traces stay linear
+pub fn build_call_tree(calls: Vec<InternalCall>) -> Option<CallNode> {
+    fn children(parent: &str, by_parent: &mut HashMap<String, Vec<InternalCall>>) -> Vec<CallNode> {
+        let mut direct = by_parent.remove(parent).unwrap_or_default();
+        direct.sort_by_key(|call| call.trace_address.rsplit('.').next().and_then(|last| last.parse::<usize>().ok()));
+
+        direct
+            .into_iter()
+            .map(|call| {
+                let calls = children(&call.trace_address, by_parent);
+                CallNode { call, calls }
+            })
+            .collect()
+    }
+
+    let mut root = None;
+    let mut by_parent: HashMap<String, Vec<InternalCall>> = HashMap::new();
+    for call in calls {
+        match call.trace_address.rsplit_once('.') {
+            Some((parent, _)) => by_parent.entry(parent.to_string()).or_default().push(call),
+            None if call.trace_address.is_empty() => root = root.or(Some(call)),
+            None => by_parent.entry(String::new()).or_default().push(call),
+        }
+    }
+    let root = root?;
+    let nested = children("", &mut by_parent);
+    Some(CallNode { call: root, calls: nested })
+}
+
+pub async fn fetch_call_trace(provider: &EthProvider, tx_hash: H256, mode: TraceMode) -> Result<Vec<InternalCall>, ProviderError> {
+    let tx_hash_str = format!("{:?}", tx_hash);
+
+    match mode {
+        TraceMode::CallTracer => {
+            let options = GethDebugTracingOptions {
+                tracer: Some(GethDebugTracerType::BuiltInTracer(GethDebugBuiltInTracerType::CallTracer)),
+                ..Default::default()
+            };
+            let frame: CallFrame = provider.request("debug_traceTransaction", (tx_hash, options)).await?;
+            Ok(calls_from_call_frame(&tx_hash_str, &frame))
+        }
+        TraceMode::Parity => {
+            let traces = provider.trace_transaction(tx_hash).await?;
+            Ok(calls_from_parity_traces(&tx_hash_str, &traces))
+        }
+    }
+}
+
+pub async fn store_transaction_trace_in_db(pool: &DBPool, calls: &[InternalCall]) -> Result<(), diesel::result::Error> {
+    use crate::schema::transaction_traces::dsl::*;
+
+    let calls = calls.to_vec();
+    with_conn(pool, move |conn| {
+        for chunk in calls.chunks(MAX_BIND_PARAMS / 12) {
+            diesel::insert_into(transaction_traces)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        Ok(())
+    }).await
+}
+
+pub async fn get_transaction_trace(pool: &DBPool, tx_hash: &str) -> Result<Vec<InternalCall>, diesel::result::Error> {
+    use crate::schema::transaction_traces::dsl::*;
+
+    let tx_hash = tx_hash.to_string();
+    with_conn(pool, move |conn| {
+        transaction_traces
+            .filter(transaction_hash.eq(tx_hash))
+            .load::<InternalCall>(conn)
+    }).await
+}
+
+/// Traces a freshly fetched transaction when tracing is configured, failures
+/// are only logged as the trace can still be fetched later through the route
+pub async fn capture_transaction_trace(pool: &DBPool, config: &Config, provider: &EthProvider, tx_hash: H256) {
+    let Some(mode) = config.eth.trace_mode else {
+        return;
+    };
+
+    match fetch_call_trace(provider, tx_hash, mode).await {
+        Ok(calls) => {
+            if let Err(e) = store_transaction_trace_in_db(pool, &calls).await {
+                error!(error = %e, "Failed to save transaction trace");
+            }
+        }
+        Err(e) => error!(error = %e, "Failed to trace transaction"),
+    }
+}
+
+#[get("/lime/eth/{hash}/trace")]
+pub async fn lime_eth_trace(path: web::Path<String>, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, req: HttpRequest) -> impl Responder {
+    let Ok(tx_hash) = H256::from_str(&path.into_inner()) else {
+        return HttpResponse::BadRequest().body("Invalid Transaction Hash");
+    };
+    let tx_hash_str = format!("{:?}", tx_hash);
+
+    let mut calls = match get_transaction_trace(&pool, &tx_hash_str).await {
+        Ok(calls) => calls,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch transaction trace");
+            return HttpResponse::InternalServerError().body("Failed to fetch transaction trace");
+        }
+    };
+
+    if calls.is_empty() {
+        let Some(mode) = config.eth.trace_mode else {
+            return HttpResponse::NotFound().body("No trace stored and call tracing is not enabled");
+        };
+        // Traces reference the transaction, make sure it is cached first.
+        // Fetching it captures the trace in the background, which may not
+        // be done yet.
+        if lookup_transaction(&pool, &config, &provider, &cache, tx_hash, refresh_requested(&req), None).await.is_none() {
+            return HttpResponse::NotFound().body("Transaction not found");
+        }
+        calls = match get_transaction_trace(&pool, &tx_hash_str).await {
+            Ok(stored) if !stored.is_empty() => stored,
+            _ => match fetch_call_trace(&provider, tx_hash, mode).await {
+                Ok(calls) => {
+                    if let Err(e) = store_transaction_trace_in_db(&pool, &calls).await {
+                        error!(error = %e, "Failed to save transaction trace");
+                    }
+                    calls
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to trace transaction");
+                    return HttpResponse::BadGateway().body("Failed to trace transaction");
+                }
+            },
+        };
+    }
+
+    match build_call_tree(calls) {
+        Some(trace) => HttpResponse::Ok().json(serde_json::json!({
+            "transactionHash": tx_hash_str,
+            "trace": trace,
+        })),
+        None => HttpResponse::NotFound().body("Transaction not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TX_HASH: &str = "0x0101010101010101010101010101010101010101010101010101010101010101";
+
+    fn call_frame(to: u64, value: u64, calls: Vec<CallFrame>) -> CallFrame {
+        CallFrame {
+            typ: "CALL".to_string(),
+            from: Address::repeat_byte(0x11),
+            to: Some(NameOrAddress::Address(Address::from_low_u64_be(to))),
+            value: Some(U256::from(value)),
+            gas: U256::from(100_000),
+            gas_used: U256::from(21_000),
+            input: Bytes::default(),
+            output: None,
+            error: None,
+            calls: Some(calls),
+            logs: None,
+        }
+    }
+
+    #[test]
+    fn test_calls_from_call_frame() {
+        let frame = call_frame(1, 0, vec![
+            call_frame(2, 5, vec![call_frame(3, 7, vec![])]),
+            call_frame(4, 9, vec![]),
+        ]);
+
+        let calls = calls_from_call_frame(TX_HASH, &frame);
+        let addresses: Vec<&str> = calls.iter().map(|call| call.trace_address.as_str()).collect();
+        assert_eq!(addresses, vec!["", "0", "0.0", "1"]);
+        assert_eq!(calls[2].depth, 2);
+        assert_eq!(calls[2].value, "7");
+        assert_eq!(calls[0].call_type, "call");
+    }
+
+    #[test]
+    fn test_calls_from_parity_traces() {
+        let json = r#"[
+            {
+                "action": {"callType": "call", "from": "0x1111111111111111111111111111111111111111", "gas": "0x186a0", "input": "0x", "to": "0x2222222222222222222222222222222222222222", "value": "0x0"},
+                "blockHash": "0x0202020202020202020202020202020202020202020202020202020202020202",
+                "blockNumber": 1,
+                "result": {"gasUsed": "0x5208", "output": "0x"},
+                "subtraces": 1,
+                "traceAddress": [],
+                "transactionHash": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "transactionPosition": 0,
+                "type": "call"
+            },
+            {
+                "action": {"callType": "delegatecall", "from": "0x2222222222222222222222222222222222222222", "gas": "0x1000", "input": "0x", "to": "0x3333333333333333333333333333333333333333", "value": "0x0"},
+                "blockHash": "0x0202020202020202020202020202020202020202020202020202020202020202",
+                "blockNumber": 1,
+                "error": "Reverted",
+                "subtraces": 0,
+                "traceAddress": [0],
+                "transactionHash": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "transactionPosition": 0,
+                "type": "call"
+            }
+        ]"#;
+        let traces: Vec<Trace> = serde_json::from_str(json).unwrap();
+
+        let calls = calls_from_parity_traces(TX_HASH, &traces);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].gas_used, 21_000);
+        assert_eq!(calls[1].trace_address, "0");
+        assert_eq!(calls[1].call_type, "delegatecall");
+        assert_eq!(calls[1].error.as_deref(), Some("Reverted"));
+    }
+
+    #[test]
+    fn test_build_call_tree() {
+        let frame = call_frame(1, 0, vec![
+            call_frame(2, 5, vec![call_frame(3, 7, vec![])]),
+            call_frame(4, 9, vec![]),
+        ]);
+        let mut calls = calls_from_call_frame(TX_HASH, &frame);
+        // Stored rows come back in no particular order
+        calls.reverse();
+
+        let tree = build_call_tree(calls).unwrap();
+        assert_eq!(tree.call.trace_address, "");
+        assert_eq!(tree.calls.len(), 2);
+        assert_eq!(tree.calls[0].call.trace_address, "0");
+        assert_eq!(tree.calls[0].calls[0].call.value, "7");
+        assert_eq!(tree.calls[1].call.trace_address, "1");
+
+        assert!(build_call_tree(Vec::new()).is_none());
+
+        let wide = call_frame(1, 0, (0..12).map(|value| call_frame(2, value, vec![])).collect());
+        let mut calls = calls_from_call_frame(TX_HASH, &wide);
+        calls.reverse();
+        let tree = build_call_tree(calls).unwrap();
+        let values: Vec<_> = tree.calls.iter().map(|node| node.call.value.clone()).collect();
+        assert_eq!(values, (0..12).map(|value| value.to_string()).collect::<Vec<_>>());
+    }
+}
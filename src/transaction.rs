@@ -1,5 +1,5 @@
-use actix_web::web;
-use std::env;
+use std::collections::HashMap;
+use std::fmt;
 use serde::{Deserialize, Serialize};
 use diesel::prelude::*;
 
@@ -7,10 +7,13 @@ use ethers::prelude::*;
 use ethers::types::{Transaction as EthersTransaction, TransactionReceipt};
 use ethers::utils::hex;
 
-use rlp::Rlp;
+use ethers::utils::rlp::{self, Decodable, RlpStream, DecoderError};
 
 use crate::DBPool;
-use super::schema::transactions;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
+use crate::metrics::MeteredClient;
+use crate::events::TransactionLog;
+use super::schema::{transaction_fees, transactions};
 
 /// Transaction Hashes as Strings
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,7 +23,7 @@ pub struct TransactionHashes {
 }
 
 /// Custom representation of transaction on the blockchain
-#[derive(Debug, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Queryable, Insertable)]
 #[diesel(table_name = transactions)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
@@ -47,87 +50,449 @@ impl From<(EthersTransaction, TransactionReceipt)> for Transaction {
             to: tx.to.map(|to| format!("{:?}", to)),
             contract_address: receipt.contract_address.map(|addr| format!("{:?}", addr)),
             logs_count: receipt.logs.len() as i64,
-            input: format!("{:?}", tx.input),
+            input: tx.input.to_string(),
             value: format!("{:?}", tx.value),
         }
     }
 }
 
-pub async fn store_transaction_in_db(pool: web::Data<DBPool>, tx: &Transaction) -> Result<(), diesel::result::Error> {
-    use crate::schema::transactions::dsl::*;
+impl Transaction {
+    /// RLP representation of a transaction, used by the `rlp` response format:
+    /// `[transactionHash, transactionStatus, blockHash, blockNumber, from, to,
+    ///   contractAddress, logsCount, input, value]`, where hashes and addresses are
+    /// their raw bytes, a missing `to` / `contractAddress` is the empty string and
+    /// the numeric fields are big-endian integers. Fails on a stored field that
+    /// doesn't parse rather than encoding it as empty.
+    fn rlp_append_checked(&self, s: &mut RlpStream) -> Result<(), String> {
+        let field_bytes = |name: &str, value: &str| {
+            hex::decode(value).map_err(|e| format!("Malformed {} of transaction {}: {}", name, self.transaction_hash, e))
+        };
+        let optional_bytes = |name: &str, value: Option<&str>| value.map(|value| field_bytes(name, value)).transpose().map(Option::unwrap_or_default);
+        let value = U256::from_dec_str(&self.value)
+            .map_err(|e| format!("Malformed value of transaction {}: {}", self.transaction_hash, e))?;
+
+        s.begin_list(10);
+        s.append(&field_bytes("transactionHash", &self.transaction_hash)?);
+        s.append(&self.transaction_status);
+        s.append(&field_bytes("blockHash", &self.block_hash)?);
+        s.append(&(self.block_number as u64));
+        s.append(&field_bytes("from", &self.from)?);
+        s.append(&optional_bytes("to", self.to.as_deref())?);
+        s.append(&optional_bytes("contractAddress", self.contract_address.as_deref())?);
+        s.append(&(self.logs_count as u64));
+        s.append(&field_bytes("input", &self.input)?);
+        s.append(&value);
+        Ok(())
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode(rlp: &rlp::Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 10 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        let optional_address = |index: usize| -> Result<Option<String>, DecoderError> {
+            let item = rlp.at(index)?;
+            if item.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(format!("{:?}", item.as_val::<Address>()?)))
+            }
+        };
+
+        Ok(Transaction {
+            transaction_hash: format!("{:?}", rlp.val_at::<H256>(0)?),
+            transaction_status: rlp.val_at(1)?,
+            block_hash: format!("{:?}", rlp.val_at::<H256>(2)?),
+            block_number: rlp.val_at::<u64>(3)? as i64,
+            from: format!("{:?}", rlp.val_at::<Address>(4)?),
+            to: optional_address(5)?,
+            contract_address: optional_address(6)?,
+            logs_count: rlp.val_at::<u64>(7)? as i64,
+            input: Bytes::from(rlp.val_at::<Vec<u8>>(8)?).to_string(),
+            value: format!("{:?}", rlp.val_at::<U256>(9)?),
+        })
+    }
+}
+
+pub fn hex_to_bytes(hex_str: &str) -> Vec<u8> {
+    hex::decode(hex_str).unwrap_or_default()
+}
 
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
+/// Encodes transactions as an RLP list of `Transaction` lists
+pub fn encode_transactions_rlp(txs: &[Transaction]) -> Result<Vec<u8>, String> {
+    let mut s = RlpStream::new_list(txs.len());
+    for tx in txs {
+        tx.rlp_append_checked(&mut s)?;
+    }
+    Ok(s.out().to_vec())
+}
 
-    // Check if the transaction already exists
-    let existing_tx: Option<Transaction> = transactions
-        .filter(transaction_hash.eq(&tx.transaction_hash))
-        .first::<Transaction>(&mut conn)
-        .optional()?;
+/// Inverse of `encode_transactions_rlp`, only needed to check responses in tests
+#[cfg(test)]
+pub fn decode_transactions_rlp(data: &[u8]) -> Result<Vec<Transaction>, DecoderError> {
+    rlp::Rlp::new(data).as_list()
+}
+
+/// Inserts the transactions not stored yet, concurrent inserts of the same
+/// hash are left to the primary key. Returns how many were new.
+pub fn insert_transactions(conn: &mut PgConnection, txs: &[Transaction]) -> Result<usize, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
 
-    if existing_tx.is_none() {
-        // Insert only if the transaction doesn't exist
-        diesel::insert_into(transactions)
-            .values(tx)
-            .execute(&mut conn)?;
+    let mut inserted = 0;
+    for chunk in txs.chunks(MAX_BIND_PARAMS / 10) {
+        inserted += diesel::insert_into(transactions)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
     }
+    Ok(inserted)
+}
 
-    Ok(())
+/// Single transaction `insert_transactions`, only needed to seed tests
+#[cfg(test)]
+pub async fn store_transaction_in_db(pool: actix_web::web::Data<DBPool>, tx: &Transaction) -> Result<(), diesel::result::Error> {
+    let tx = tx.clone();
+    with_conn(&pool, move |conn| insert_transactions(conn, &[tx]).map(|_| ())).await
+}
+
+/// Successful transaction with placeholder sender, value and payload, only
+/// needed to seed tests. Tests override the fields they check.
+#[cfg(test)]
+pub fn test_transaction(tx_hash: &str, block_hash: &str, block_number: i64) -> Transaction {
+    Transaction {
+        transaction_hash: tx_hash.to_string(),
+        transaction_status: true,
+        block_hash: block_hash.to_string(),
+        block_number,
+        from: "0x1111111111111111111111111111111111111111".to_string(),
+        to: None,
+        contract_address: None,
+        logs_count: 0,
+        input: "0x".to_string(),
+        value: "0".to_string(),
+    }
 }
 
 pub async fn get_transaction_from_db(pool: &DBPool, tx_hash: &str) -> Result<Option<Transaction>, diesel::result::Error> {
     use crate::schema::transactions::dsl::*;
 
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    transactions.filter(transaction_hash.eq(tx_hash)).first::<Transaction>(&mut conn).optional()
+    let tx_hash = tx_hash.to_string();
+    with_conn(pool, move |conn| transactions.filter(transaction_hash.eq(tx_hash)).first::<Transaction>(conn).optional()).await
+}
+
+/// The stored ones of the given transactions, in no particular order
+pub async fn get_transactions_from_db(pool: &DBPool, tx_hashes: &[String]) -> Result<Vec<Transaction>, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+
+    let tx_hashes = tx_hashes.to_vec();
+    with_conn(pool, move |conn| transactions.filter(transaction_hash.eq_any(tx_hashes)).load::<Transaction>(conn)).await
 }
 
 pub async fn get_all_transactions_from_db(pool: &DBPool) -> Result<Vec<Transaction>, diesel::result::Error> {
     use crate::schema::transactions::dsl::*;
 
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    transactions.load::<Transaction>(&mut conn)
+    with_conn(pool, |conn| transactions.load::<Transaction>(conn)).await
+}
+
+/// Up to `limit` transactions ordered by hash, starting after `after`. Used to
+/// walk the whole table in chunks.
+pub async fn get_transactions_page_from_db(pool: &DBPool, after: Option<&str>, limit: i64) -> Result<Vec<Transaction>, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+
+    let after = after.unwrap_or_default().to_string();
+    with_conn(pool, move |conn| {
+        transactions
+            .filter(transaction_hash.gt(after))
+            .order(transaction_hash)
+            .limit(limit)
+            .load::<Transaction>(conn)
+    }).await
+}
+
+/// Restricts a scan of the `transactions` table, unset fields match everything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionFilter {
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// Matches `from`, `to` or `contractAddress`
+    pub address: Option<String>,
+}
+
+/// Same as `get_transactions_page_from_db`, restricted by `filter`
+pub async fn get_filtered_transactions_page_from_db(pool: &DBPool, filter: &TransactionFilter, after: Option<&str>, limit: i64) -> Result<Vec<Transaction>, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+
+    let filter = filter.clone();
+    let after = after.unwrap_or_default().to_string();
+    with_conn(pool, move |conn| {
+        let mut query = transactions
+            .filter(transaction_hash.gt(after))
+            .into_boxed();
+        if let Some(from_block) = filter.from_block {
+            query = query.filter(block_number.ge(from_block));
+        }
+        if let Some(to_block) = filter.to_block {
+            query = query.filter(block_number.le(to_block));
+        }
+        if let Some(address) = filter.address {
+            query = query.filter(from.eq(address.clone()).or(to.eq(address.clone())).or(contract_address.eq(address)));
+        }
+
+        query
+            .order(transaction_hash)
+            .limit(limit)
+            .load::<Transaction>(conn)
+    }).await
+}
+
+pub async fn get_block_transactions_from_db(pool: &DBPool, block: &str) -> Result<Vec<Transaction>, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+
+    let block = block.to_string();
+    with_conn(pool, move |conn| {
+        transactions
+            .filter(block_hash.eq(block))
+            .load::<Transaction>(conn)
+    }).await
 }
 
-pub async fn fetch_transaction(tx_hash: H256) -> Option<Transaction> {
-    let eth_rpc_url = env::var("ETH_NODE_URL").unwrap_or_else(|_| "ETH_URL".to_string());
-    let provider = ethers::providers::Provider::<ethers::providers::Http>::try_from(eth_rpc_url).unwrap();
+/// Provider of the ETH node, its requests are counted on `/metrics`
+pub type EthProvider = Provider<MeteredClient<Http>>;
 
-    if let Ok(Some(tx)) = provider.get_transaction(tx_hash).await {
-        if let Ok(Some(receipt)) = provider.get_transaction_receipt(tx_hash).await {
-            return Some(Transaction::from((tx, receipt)));
+/// What the sender paid for a mined transaction, amounts in wei
+#[derive(Debug, Clone, PartialEq, Queryable, Insertable)]
+#[diesel(table_name = transaction_fees)]
+pub struct TransactionFee {
+    pub transaction_hash: String,
+    pub gas_used: i64,
+    pub effective_gas_price: String,
+    pub fee: String,
+}
+
+impl From<(&EthersTransaction, &TransactionReceipt)> for TransactionFee {
+    fn from((tx, receipt): (&EthersTransaction, &TransactionReceipt)) -> Self {
+        let gas_used = receipt.gas_used.unwrap_or_default();
+        // Receipts of pre-London nodes don't carry the effective price
+        let gas_price = receipt.effective_gas_price.or(tx.gas_price).unwrap_or_default();
+        Self {
+            transaction_hash: format!("{:?}", tx.hash),
+            gas_used: gas_used.low_u64() as i64,
+            effective_gas_price: gas_price.to_string(),
+            fee: gas_used.saturating_mul(gas_price).to_string(),
         }
     }
-    None
 }
 
+pub fn insert_transaction_fees(conn: &mut PgConnection, fees: &[TransactionFee]) -> Result<usize, diesel::result::Error> {
+    use crate::schema::transaction_fees::dsl::*;
+
+    let mut inserted = 0;
+    for chunk in fees.chunks(MAX_BIND_PARAMS / 4) {
+        inserted += diesel::insert_into(transaction_fees)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(inserted)
+}
+
+/// Fees of the given transactions in wei, keyed by transaction hash
+pub async fn get_transaction_fees(pool: &DBPool, tx_hashes: &[String]) -> Result<HashMap<String, String>, diesel::result::Error> {
+    use crate::schema::transaction_fees::dsl::*;
+
+    let tx_hashes = tx_hashes.to_vec();
+    with_conn(pool, move |conn| {
+        Ok(transaction_fees
+            .filter(transaction_hash.eq_any(tx_hashes))
+            .select((transaction_hash, fee))
+            .load::<(String, String)>(conn)?
+            .into_iter()
+            .collect())
+    }).await
+}
+
+/// Everything fetched from the node for a single transaction
 #[derive(Debug)]
+pub struct FetchedTransaction {
+    pub transaction: Transaction,
+    pub fee: TransactionFee,
+    pub logs: Vec<TransactionLog>,
+}
+
+/// What the node answered for a transaction hash
+pub enum NodeTransaction {
+    Mined(Box<FetchedTransaction>),
+    /// Known to the node but not in a block yet, so there's no receipt to store
+    Pending,
+    Unknown,
+}
+
+#[tracing::instrument(skip_all, fields(tx_hash = ?tx_hash))]
+pub async fn fetch_transaction(provider: &EthProvider, tx_hash: H256) -> Result<NodeTransaction, ProviderError> {
+    let Some(tx) = provider.get_transaction(tx_hash).await? else {
+        return Ok(NodeTransaction::Unknown);
+    };
+    let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+        return Ok(NodeTransaction::Pending);
+    };
+    let logs = receipt.logs.iter().map(TransactionLog::from).collect();
+    let fee = TransactionFee::from((&tx, &receipt));
+    Ok(NodeTransaction::Mined(Box::new(FetchedTransaction {
+        transaction: Transaction::from((tx, receipt)),
+        fee,
+        logs,
+    })))
+}
+
+/// Whether the node knows the transaction at all, mined or still pending
+pub async fn transaction_exists_on_chain(provider: &EthProvider, tx_hash: H256) -> Result<bool, ProviderError> {
+    Ok(provider.get_transaction(tx_hash).await?.is_some())
+}
+
+/// Why an RLP hex list of hashes couldn't be decoded. Offsets in `InvalidHex`
+/// are character positions in the input string, all others are byte positions
+/// in the decoded RLP payload.
+#[derive(Debug, PartialEq)]
 pub enum DecodeError {
-    InvalidHex,
-    InvalidHashLen,
+    OddLength { len: usize },
+    InvalidHex { offset: usize },
+    UnexpectedEnd { offset: usize },
+    NotAList { offset: usize },
+    NestedList { offset: usize },
+    NonCanonicalLength { offset: usize },
+    TrailingBytes { offset: usize },
+    InvalidHashLen { offset: usize, len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::OddLength { len } => write!(f, "Invalid Hex String: odd number of hex digits ({})", len),
+            DecodeError::InvalidHex { offset } => write!(f, "Invalid Hex String: invalid character at offset {}", offset),
+            DecodeError::UnexpectedEnd { offset } => write!(f, "Invalid RLP: unexpected end of input at byte {}", offset),
+            DecodeError::NotAList { offset } => write!(f, "Invalid RLP: expected a list at byte {}", offset),
+            DecodeError::NestedList { offset } => write!(f, "Invalid RLP: unexpected nested list at byte {}", offset),
+            DecodeError::NonCanonicalLength { offset } => write!(f, "Invalid RLP: non-canonical length prefix at byte {}", offset),
+            DecodeError::TrailingBytes { offset } => write!(f, "Invalid RLP: trailing bytes after the list at byte {}", offset),
+            DecodeError::InvalidHashLen { offset, len } => write!(f, "Invalid Hash Length: {} bytes at byte {}", len, offset),
+        }
+    }
 }
 
+/// Decodes hex, with or without a `0x` prefix (surrounding whitespace is ignored), into an RLP list of hashes
 pub fn decode_rlp_hex(rlp_hex: &str) -> Result<Vec<H256>, DecodeError> {
-    let rlp_data = match hex::decode(rlp_hex) {
-        Ok(data) => data,
-        Err(_) => return Err(DecodeError::InvalidHex),
-    };
+    let rlp_hex = rlp_hex.trim();
+    let digits = rlp_hex
+        .strip_prefix("0x")
+        .or_else(|| rlp_hex.strip_prefix("0X"))
+        .unwrap_or(rlp_hex);
+    let prefix_len = rlp_hex.len() - digits.len();
 
-    let rlp = Rlp::new(&rlp_data).as_list::<Vec<u8>>();
+    if let Some(offset) = digits.find(|c: char| !c.is_ascii_hexdigit()) {
+        return Err(DecodeError::InvalidHex { offset: offset + prefix_len });
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(DecodeError::OddLength { len: digits.len() });
+    }
+
+    let rlp_data = hex::decode(digits).map_err(|_| DecodeError::InvalidHex { offset: prefix_len })?;
+    decode_rlp_bytes(&rlp_data)
+}
+
+/// Decodes a flat RLP list of 32 byte strings, rejecting anything that isn't canonical RLP
+pub fn decode_rlp_bytes(rlp_data: &[u8]) -> Result<Vec<H256>, DecodeError> {
+    let list = read_rlp_header(rlp_data, 0, rlp_data.len())?;
+    if !list.is_list {
+        return Err(DecodeError::NotAList { offset: 0 });
+    }
+
+    let list_end = list.payload_offset + list.payload_len;
+    if list_end < rlp_data.len() {
+        return Err(DecodeError::TrailingBytes { offset: list_end });
+    }
 
     let mut hashes: Vec<H256> = Vec::new();
-    for hash_bytes in rlp {
-        if hash_bytes.len() != 32 {
-            return Err(DecodeError::InvalidHashLen);
+    let mut offset = list.payload_offset;
+    while offset < list_end {
+        let item = read_rlp_header(rlp_data, offset, list_end)?;
+        if item.is_list {
+            return Err(DecodeError::NestedList { offset });
         }
-        hashes.push(H256::from_slice(&hash_bytes));
+        if item.payload_len != 32 {
+            return Err(DecodeError::InvalidHashLen { offset, len: item.payload_len });
+        }
+
+        hashes.push(H256::from_slice(&rlp_data[item.payload_offset..item.payload_offset + 32]));
+        offset = item.payload_offset + item.payload_len;
     }
 
     Ok(hashes)
 }
 
+struct RlpHeader {
+    is_list: bool,
+    payload_offset: usize,
+    payload_len: usize,
+}
+
+/// Reads the RLP item header at `offset`, making sure its payload fits before `end`
+fn read_rlp_header(data: &[u8], offset: usize, end: usize) -> Result<RlpHeader, DecodeError> {
+    let prefix = *data.get(offset).filter(|_| offset < end).ok_or(DecodeError::UnexpectedEnd { offset })?;
+
+    let (is_list, payload_offset, payload_len) = match prefix {
+        // Single byte, it is its own payload
+        0x00..=0x7f => (false, offset, 1),
+        0x80..=0xb7 => {
+            let len = (prefix - 0x80) as usize;
+            // A single byte below 0x80 must be encoded as itself
+            if len == 1 && data.get(offset + 1).is_some_and(|byte| *byte < 0x80) {
+                return Err(DecodeError::NonCanonicalLength { offset });
+            }
+            (false, offset + 1, len)
+        }
+        0xb8..=0xbf => {
+            let len_of_len = (prefix - 0xb7) as usize;
+            (false, offset + 1 + len_of_len, read_long_length(data, offset, len_of_len, end)?)
+        }
+        0xc0..=0xf7 => (true, offset + 1, (prefix - 0xc0) as usize),
+        0xf8..=0xff => {
+            let len_of_len = (prefix - 0xf7) as usize;
+            (true, offset + 1 + len_of_len, read_long_length(data, offset, len_of_len, end)?)
+        }
+    };
+
+    if payload_offset.checked_add(payload_len).is_none_or(|payload_end| payload_end > end) {
+        return Err(DecodeError::UnexpectedEnd { offset: end });
+    }
+
+    Ok(RlpHeader { is_list, payload_offset, payload_len })
+}
+
+/// Big-endian length following a long string / long list prefix at `offset`
+fn read_long_length(data: &[u8], offset: usize, len_of_len: usize, end: usize) -> Result<usize, DecodeError> {
+    let len_bytes = data
+        .get(offset + 1..offset + 1 + len_of_len)
+        .filter(|_| offset + 1 + len_of_len <= end)
+        .ok_or(DecodeError::UnexpectedEnd { offset: end })?;
+
+    // No leading zeroes, and short payloads must use the short form
+    if len_bytes[0] == 0 || len_of_len > std::mem::size_of::<usize>() {
+        return Err(DecodeError::NonCanonicalLength { offset });
+    }
+    let len = len_bytes.iter().fold(0usize, |len, byte| (len << 8) | *byte as usize);
+    if len < 56 {
+        return Err(DecodeError::NonCanonicalLength { offset });
+    }
+
+    Ok(len)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_decode_rlp_hex_valid() {
@@ -140,17 +505,130 @@ mod tests {
         assert_eq!(decoded, expected_hashes);
     }
 
+    #[test]
+    fn test_transactions_rlp_roundtrip() {
+        let txs = vec![
+            Transaction {
+                to: Some("0x2222222222222222222222222222222222222222".to_string()),
+                logs_count: 2,
+                input: "0xa9059cbb".to_string(),
+                value: "500000000000000000".to_string(),
+                ..test_transaction("0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542", "0x2f5bf15391119b4851c619f97d15ec9c0bd580eb034c29ce32e4c35bb3f288eb", 5703601)
+            },
+            Transaction {
+                transaction_status: false,
+                contract_address: Some("0x3333333333333333333333333333333333333333".to_string()),
+                ..test_transaction("0x22f4a0e8243f6becd1e4e31fce147244a5de7ce604080854c516b324a186a59e", "0x0000000000000000000000000000000000000000000000000000000000000001", 0)
+            },
+        ];
+
+        let encoded = encode_transactions_rlp(&txs).unwrap();
+        let decoded = decode_transactions_rlp(&encoded).unwrap();
+        assert_eq!(decoded, txs);
+    }
+
+    #[test]
+    fn test_encode_transactions_rlp_malformed_field() {
+        let tx = Transaction {
+            input: "Bytes(0xa9059cbb)".to_string(),
+            ..test_transaction("0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542", "0x2f5bf15391119b4851c619f97d15ec9c0bd580eb034c29ce32e4c35bb3f288eb", 5703601)
+        };
+        let error = encode_transactions_rlp(&[tx]).unwrap_err();
+        assert!(error.starts_with("Malformed input of transaction 0x6d61"), "{}", error);
+    }
+
     #[test]
     fn test_decode_rlp_hex_invalid_hex() {
-        let rlp_hex = "zzzz";
+        let rlp_hex = "0xzzzz";
         let decoded = decode_rlp_hex(rlp_hex);
-        assert!(matches!(decoded, Err(DecodeError::InvalidHex)));
+        assert!(matches!(decoded, Err(DecodeError::InvalidHex { offset: 2 })));
     }
 
     #[test]
     fn test_decode_rlp_hex_invalid_hash_len() {
-        let rlp_hex = "0xe1a06d61b62233334ebfb12311313131313111fdf542cf4948ef2831b65f0f1fe542231331";
+        let rlp_hex = "0xe2a16d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe54223";
         let decoded = decode_rlp_hex(rlp_hex);
-        assert!(matches!(decoded, Err(DecodeError::InvalidHashLen)));
+        assert!(matches!(decoded, Err(DecodeError::InvalidHashLen { offset: 1, len: 33 })));
+    }
+
+    #[test]
+    fn test_decode_rlp_hex_optional_prefix() {
+        let rlp_hex = "e1a06d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542";
+        let decoded = decode_rlp_hex(rlp_hex).unwrap();
+        assert_eq!(decode_rlp_hex(&format!("0x{}", rlp_hex)), Ok(decoded.clone()));
+        // Surrounding whitespace and an upper case prefix are fine
+        assert_eq!(decode_rlp_hex(&format!(" 0X{}\n", rlp_hex)), Ok(decoded));
+        // Offsets still point into the input
+        assert_eq!(decode_rlp_hex("zz"), Err(DecodeError::InvalidHex { offset: 0 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_hex_odd_length() {
+        assert_eq!(decode_rlp_hex("0xe1a"), Err(DecodeError::OddLength { len: 3 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_hex_trailing_bytes() {
+        let rlp_hex = "0xe1a06d61b62233334ebfb12311313131313111fdf542cf4948ef2831b65f0f1fe542231331";
+        assert_eq!(decode_rlp_hex(rlp_hex), Err(DecodeError::TrailingBytes { offset: 34 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_bytes_not_a_list() {
+        let mut rlp_data = vec![0xa0];
+        rlp_data.extend_from_slice(&[0x11; 32]);
+        assert_eq!(decode_rlp_bytes(&rlp_data), Err(DecodeError::NotAList { offset: 0 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_bytes_nested_list() {
+        assert_eq!(decode_rlp_bytes(&[0xc2, 0xc1, 0x01]), Err(DecodeError::NestedList { offset: 1 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_bytes_non_canonical_length() {
+        // Single byte below 0x80 wrapped in a string prefix
+        assert_eq!(decode_rlp_bytes(&[0xc2, 0x81, 0x01]), Err(DecodeError::NonCanonicalLength { offset: 1 }));
+        // Long list form used for a short payload
+        assert_eq!(decode_rlp_bytes(&[0xf8, 0x01, 0x01]), Err(DecodeError::NonCanonicalLength { offset: 0 }));
+        // Leading zero in the length
+        assert_eq!(decode_rlp_bytes(&[0xf9, 0x00, 0x40]), Err(DecodeError::NonCanonicalLength { offset: 0 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_bytes_unexpected_end() {
+        assert_eq!(decode_rlp_bytes(&[]), Err(DecodeError::UnexpectedEnd { offset: 0 }));
+        assert_eq!(decode_rlp_bytes(&[0xe1, 0xa0, 0x11]), Err(DecodeError::UnexpectedEnd { offset: 3 }));
+    }
+
+    #[test]
+    fn test_decode_rlp_bytes_empty_list() {
+        assert_eq!(decode_rlp_bytes(&[0xc0]), Ok(vec![]));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_rlp_hashes_roundtrip(raw_hashes in prop::collection::vec(prop::array::uniform32(any::<u8>()), 0..64)) {
+            let hashes: Vec<H256> = raw_hashes.into_iter().map(H256).collect();
+            let rlp_hex = format!("0x{}", hex::encode(rlp::encode_list(&hashes)));
+            prop_assert_eq!(decode_rlp_hex(&rlp_hex), Ok(hashes));
+        }
+
+        #[test]
+        fn prop_rlp_decode_never_panics(data in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode_rlp_bytes(&data);
+        }
+
+        #[test]
+        fn prop_rlp_trailing_bytes_rejected(
+            raw_hashes in prop::collection::vec(prop::array::uniform32(any::<u8>()), 0..8),
+            trailing in prop::collection::vec(any::<u8>(), 1..8),
+        ) {
+            let hashes: Vec<H256> = raw_hashes.into_iter().map(H256).collect();
+            let mut rlp_data = rlp::encode_list(&hashes).to_vec();
+            let list_end = rlp_data.len();
+            rlp_data.extend_from_slice(&trailing);
+            prop_assert_eq!(decode_rlp_bytes(&rlp_data), Err(DecodeError::TrailingBytes { offset: list_end }));
+        }
     }
 }
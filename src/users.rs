@@ -1,13 +1,26 @@
 use actix_web::{web, HttpRequest};
+use actix_web::error::ErrorInternalServerError;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
 use diesel::prelude::*;
-use diesel::dsl::exists;
-use diesel::select;
+use tracing::error;
 
 use crate::DBPool;
+use crate::db::{MAX_BIND_PARAMS, with_conn};
 use crate::auth::verify_jwt;
-use crate::schema::users_searches;
+use crate::config::Config;
+use crate::metrics::record_jwt_failure;
+use crate::schema::{users, users_searches};
 use crate::transaction::Transaction;
 
+#[derive(Debug, Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUser {
+    pub username: String,
+    pub password_hash: String,
+}
+
 #[derive(Debug, Insertable)]
 #[diesel(table_name = users_searches)]
 pub struct UserSearch {
@@ -17,66 +30,169 @@ pub struct UserSearch {
 
 
 // Not needed to be async actually
-pub async fn get_user_from_token(req: &HttpRequest) -> Option<String> {
+pub async fn get_user_from_token(req: &HttpRequest) -> Result<Option<String>, actix_web::Error> {
+    let Some(config) = req.app_data::<web::Data<Config>>() else {
+        error!("Config is not registered as app data, can't verify AUTH_TOKEN");
+        return Err(ErrorInternalServerError("Failed to verify AUTH_TOKEN"));
+    };
     if let Some(header) = req.headers().get("AUTH_TOKEN") {
         if let Ok(token) = header.to_str() {
-            let claim_result = verify_jwt(token);
+            let claim_result = verify_jwt(config, token);
             match claim_result  {
                 Ok(claim) => {
-                    return Some(claim.username);
+                    return Ok(Some(claim.username));
                 }
-                Err(_e) => {
-                    return None;
+                Err(e) => {
+                    record_jwt_failure(&e);
+                    return Ok(None);
                 }
             }
         }
     }
-    None
+    Ok(None)
 }
 
-pub async fn store_user_search(pool: web::Data<DBPool>, user_searching: &str , transaction_hash_search: &str) -> Result<(), diesel::result::Error> {
-
+/// Records that `user_searching` looked the transactions up, searches already
+/// recorded (possibly by a concurrent request) are kept as they are
+pub fn insert_user_searches(conn: &mut PgConnection, user_searching: &str, tx_hashes: &[String]) -> Result<usize, diesel::result::Error> {
     use crate::schema::users_searches::dsl::*;
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
 
-    let user_search_exists: bool = select(exists(
-            users_searches
-                .filter(username.eq(&user_searching))
-                .filter(transaction_hash.eq(&transaction_hash_search))))
-        .get_result(&mut conn)?;
+    let new_searches: Vec<UserSearch> = tx_hashes
+        .iter()
+        .map(|tx_hash| UserSearch { username: user_searching.to_string(), transaction_hash: tx_hash.clone() })
+        .collect();
+    let mut inserted = 0;
+    for chunk in new_searches.chunks(MAX_BIND_PARAMS / 2) {
+        inserted += diesel::insert_into(users_searches)
+            .values(chunk)
+            .on_conflict((username, transaction_hash))
+            .do_nothing()
+            .execute(conn)?;
+    }
+    Ok(inserted)
+}
 
-    if !user_search_exists {
-        let new_search = UserSearch {
-            username: user_searching.to_string(),
-            transaction_hash: transaction_hash_search.to_string(),
-        };
+/// Single search `insert_user_searches`, only needed to seed tests
+#[cfg(test)]
+pub async fn store_user_search(pool: web::Data<DBPool>, user_searching: &str , transaction_hash_search: &str) -> Result<(), diesel::result::Error> {
 
+    let user_searching = user_searching.to_string();
+    let tx_hashes = [transaction_hash_search.to_string()];
+    with_conn(&pool, move |conn| insert_user_searches(conn, &user_searching, &tx_hashes).map(|_| ())).await
+}
 
-        diesel::insert_into(users_searches)
-            .values(&new_search)
-            .execute(&mut conn)?;
-    }
+pub async fn get_user_search_transactions(pool: &web::Data<DBPool>, username: &str) -> Result<Vec<Transaction>, diesel::result::Error> {
+    use crate::schema::transactions::dsl::{transactions as transactions_table, transaction_hash as transactions_hash};
+    use crate::schema::users_searches::dsl::{users_searches as users_searches_table, transaction_hash as user_search_transaction_hash};
 
-    Ok(())
+    let username = username.to_string();
+    with_conn(pool, move |conn| {
+        // Fetch all transaction hashes for the user
+        let user_searches = users_searches_table
+            .filter(users_searches::username.eq(username))
+            .select(user_search_transaction_hash)
+            .distinct()
+            .load::<String>(conn)?;
+
+        // Fetch all transactions for the user's searches
+        let transactions = transactions_table
+            .filter(transactions_hash.eq_any(user_searches))
+            .load::<Transaction>(conn)?;
+
+        Ok(transactions)
+    }).await
 }
 
-pub async fn get_user_search_transactions(pool: &web::Data<DBPool>, username: &str) -> Result<Vec<Transaction>, diesel::result::Error> {
+/// Paged variant of `get_user_search_transactions`, ordered by hash
+pub async fn get_user_search_transactions_page(pool: &DBPool, username: &str, after: Option<&str>, limit: i64) -> Result<Vec<Transaction>, diesel::result::Error> {
     use crate::schema::transactions::dsl::{transactions as transactions_table, transaction_hash as transactions_hash};
     use crate::schema::users_searches::dsl::{users_searches as users_searches_table, transaction_hash as user_search_transaction_hash};
 
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
+    let username = username.to_string();
+    let after = after.unwrap_or_default().to_string();
+    with_conn(pool, move |conn| {
+        let user_searches = users_searches_table
+            .filter(users_searches::username.eq(username))
+            .select(user_search_transaction_hash);
+
+        transactions_table
+            .filter(transactions_hash.eq_any(user_searches))
+            .filter(transactions_hash.gt(after))
+            .order(transactions_hash)
+            .limit(limit)
+            .load::<Transaction>(conn)
+    }).await
+}
 
-    // Fetch all transaction hashes for the user
-    let user_searches = users_searches_table
-        .filter(users_searches::username.eq(username))
-        .select(user_search_transaction_hash)
-        .distinct()
-        .load::<String>(&mut conn)?;
+/// Argon2 hash of `password` with a random salt, in the PHC string format
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
 
-    // Fetch all transactions for the user's searches
-    let transactions = transactions_table
-        .filter(transactions_hash.eq_any(user_searches))
-        .load::<Transaction>(&mut conn)?;
+/// Whether `password` matches a hash from `hash_password`, `false` for malformed hashes
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
 
-    Ok(transactions)
+/// Whether `name` is a registered user with this `password`. The (deliberately
+/// slow) hash check runs on the blocking thread pool along with the query.
+pub async fn verify_credentials(pool: &DBPool, name: &str, password: &str) -> Result<bool, diesel::result::Error> {
+    let name = name.to_string();
+    let password = password.to_string();
+    with_conn(pool, move |conn| {
+        let password_hash = users::table
+            .find(name)
+            .select(users::password_hash)
+            .first::<String>(conn)
+            .optional()?;
+        Ok(password_hash.is_some_and(|password_hash| verify_password(&password, &password_hash)))
+    }).await
+}
+
+/// Registers a user, `false` if the name is already taken
+pub async fn add_user(pool: &DBPool, name: &str, password: &str) -> Result<bool, String> {
+    let new_user = NewUser { username: name.to_string(), password_hash: hash_password(password)? };
+    with_conn(pool, move |conn| {
+        diesel::insert_into(users::table)
+            .values(&new_user)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map(|inserted| inserted == 1)
+    }).await.map_err(|e| e.to_string())
+}
+
+/// Removes a user along with their search history, `false` if there was no such user
+pub async fn remove_user(pool: &DBPool, name: &str) -> Result<bool, diesel::result::Error> {
+    let name = name.to_string();
+    with_conn(pool, move |conn| {
+        conn.transaction(|conn| {
+            diesel::delete(users_searches::table.filter(users_searches::username.eq(&name))).execute(conn)?;
+            diesel::delete(users::table.find(&name)).execute(conn).map(|deleted| deleted == 1)
+        })
+    }).await
+}
+
+/// Registered usernames in alphabetical order
+pub async fn list_users(pool: &DBPool) -> Result<Vec<String>, diesel::result::Error> {
+    with_conn(pool, |conn| users::table.select(users::username).order(users::username).load(conn)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hashing() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert_ne!(hash, hash_password("hunter2").unwrap());
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not a hash"));
+    }
 }
@@ -0,0 +1,228 @@
+use std::str::FromStr;
+
+use actix_web::{web, get, Error, HttpRequest, HttpResponse};
+use actix_web::rt::task::JoinHandle;
+use actix_ws::{Message, Session};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{Instrument, Span};
+
+use ethers::types::H256;
+
+use crate::auth::verify_jwt;
+use crate::cache::TransactionCache;
+use crate::config::Config;
+use crate::routes::lookup_transaction;
+use crate::prices::CsvPriceSource;
+use crate::response::{ResponseOptions, transaction_views};
+use crate::transaction::{EthProvider, decode_rlp_hex};
+use crate::users::get_user_from_token;
+use crate::DBPool;
+
+/// Lookups of a frame in flight at once, so one client can't take over the node and the database pool
+const MAX_CONCURRENT_LOOKUPS: usize = 8;
+
+/// Frames of a socket being looked up at once, further frames are answered with an error until one finishes
+const MAX_PENDING_FRAMES: usize = 4;
+
+/// Query parameters accepted on connect. Browsers can't set custom headers
+/// on a WebSocket handshake, so the JWT may be passed as `?token=` instead
+/// of the `AUTH_TOKEN` header.
+#[derive(Debug, Deserialize)]
+pub struct ConnectParams {
+    pub token: Option<String>,
+    /// Ask the node again for hashes remembered as unknown, for every lookup on the socket
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// A single lookup request sent by the client over the socket
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum SubscriptionRequest {
+    Hashes {
+        #[serde(rename = "transactionHashes")]
+        hashes: Vec<String>,
+    },
+    RlpHex {
+        rlphex: String,
+    },
+}
+
+/// Message pushed back to the client, one per requested hash
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionResult {
+    pub transaction_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SubscriptionResult {
+    fn error(transaction_hash: &str, error: &str) -> Self {
+        SubscriptionResult {
+            transaction_hash: transaction_hash.to_owned(),
+            transaction: None,
+            error: Some(error.to_owned()),
+        }
+    }
+}
+
+/// Message pushed back to the client for a frame that couldn't be handled as a whole
+#[derive(Debug, Serialize)]
+pub struct FrameError {
+    pub error: String,
+}
+
+/// Turns a raw text frame into the list of hashes to look up, at most `max_batch_size` of them
+pub fn parse_subscription_request(text: &str, max_batch_size: usize) -> Result<Vec<H256>, String> {
+    let request: SubscriptionRequest = serde_json::from_str(text)
+        .map_err(|_| "Invalid request, expected `transactionHashes` or `rlphex`".to_string())?;
+
+    let hashes: Vec<H256> = match request {
+        SubscriptionRequest::Hashes { hashes } => hashes
+            .iter()
+            .map(|hash| H256::from_str(hash).map_err(|_| "Invalid Transaction Hash provided!".to_string()))
+            .collect::<Result<_, _>>()?,
+        SubscriptionRequest::RlpHex { rlphex } => decode_rlp_hex(&rlphex).map_err(|e| e.to_string())?,
+    };
+    if hashes.len() > max_batch_size {
+        return Err(format!("Batch size exceeds the maximum of {} hashes", max_batch_size));
+    }
+    Ok(hashes)
+}
+
+async fn send_result<T: Serialize>(session: &mut Session, result: &T) -> bool {
+    match serde_json::to_string(result) {
+        Ok(text) => session.text(text).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+/// Resolves the hashes of one frame concurrently (up to `MAX_CONCURRENT_LOOKUPS` at a time) and pushes each one as soon as it's ready
+#[allow(clippy::too_many_arguments)]
+async fn lookup_frame(mut session: Session, hashes: Vec<H256>, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, csv_prices: web::Data<CsvPriceSource>, user: Option<String>, refresh: bool, options: ResponseOptions) {
+    let mut lookups = stream::iter(hashes)
+        .map(|hash| {
+            let pool = pool.clone();
+            let config = config.clone();
+            let provider = provider.clone();
+            let cache = cache.clone();
+            let user = user.clone();
+            async move { (hash, lookup_transaction(&pool, &config, &provider, &cache, hash, refresh, user.as_deref()).await) }
+        })
+        .buffer_unordered(MAX_CONCURRENT_LOOKUPS);
+
+    while let Some((hash, transaction)) = lookups.next().await {
+        let transaction_hash = format!("{:?}", hash);
+        let result = match transaction {
+            Some(tx) => SubscriptionResult {
+                transaction_hash,
+                transaction: transaction_views(&pool, &config, &csv_prices, vec![tx]).await.pop().map(|view| options.format_transaction(&view)),
+                error: None,
+            },
+            None => SubscriptionResult::error(&transaction_hash, "Transaction not found"),
+        };
+        if !send_result(&mut session, &result).await {
+            return;
+        }
+    }
+}
+
+#[get("/lime/ws")]
+pub async fn lime_ws(req: HttpRequest, body: web::Payload, pool: web::Data<DBPool>, config: web::Data<Config>, provider: web::Data<EthProvider>, cache: web::Data<TransactionCache>, csv_prices: web::Data<CsvPriceSource>) -> Result<HttpResponse, Error> {
+    let params = web::Query::<ConnectParams>::from_query(req.query_string())?;
+    let user = match (get_user_from_token(&req).await?, &params.token) {
+        (Some(user), _) => Some(user),
+        (None, Some(token)) => match verify_jwt(&config, token) {
+            Ok(claims) => Some(claims.username),
+            Err(_) => return Ok(HttpResponse::Unauthorized().body("Invalid or missing AUTH_TOKEN")),
+        },
+        (None, None) => None,
+    };
+    let options = match ResponseOptions::from_request(&req) {
+        Ok(options) => options,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+    };
+
+    let refresh = params.refresh;
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+
+    actix_web::rt::spawn(async move {
+        // Lookups run on their own tasks, so pings and the close frame are answered while they're in flight
+        let mut lookups: Vec<JoinHandle<()>> = Vec::new();
+        let mut close_reason = None;
+
+        while let Some(Ok(msg)) = msg_stream.next().await {
+            match msg {
+                Message::Text(text) => {
+                    let hashes = match parse_subscription_request(&text, config.server.max_batch_size) {
+                        Ok(hashes) => hashes,
+                        Err(error) => {
+                            if !send_result(&mut session, &FrameError { error }).await {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+
+                    lookups.retain(|lookup| !lookup.is_finished());
+                    if lookups.len() >= MAX_PENDING_FRAMES {
+                        let error = format!("Too many requests in flight, at most {} at a time", MAX_PENDING_FRAMES);
+                        if !send_result(&mut session, &FrameError { error }).await {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let lookup = lookup_frame(session.clone(), hashes, pool.clone(), config.clone(), provider.clone(), cache.clone(), csv_prices.clone(), user.clone(), refresh, options);
+                    lookups.push(actix_web::rt::spawn(lookup.instrument(Span::current())));
+                }
+                Message::Ping(bytes) if session.pong(&bytes).await.is_err() => break,
+                Message::Close(reason) => {
+                    close_reason = reason;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        for lookup in lookups {
+            lookup.abort();
+        }
+        let _ = session.close(close_reason).await;
+    }.instrument(Span::current()));
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscription_request_hashes() {
+        let text = r#"{"transactionHashes": ["0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542"]}"#;
+        let hashes = parse_subscription_request(text, 500).unwrap();
+        assert_eq!(hashes, vec![H256::from_str("0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542").unwrap()]);
+    }
+
+    #[test]
+    fn test_parse_subscription_request_rlphex() {
+        let text = r#"{"rlphex": "0xe1a06d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542"}"#;
+        let hashes = parse_subscription_request(text, 500).unwrap();
+        assert_eq!(hashes.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_subscription_request_invalid() {
+        assert!(parse_subscription_request("not json", 500).is_err());
+        assert!(parse_subscription_request(r#"{"transactionHashes": ["0x12"]}"#, 500).is_err());
+
+        let text = r#"{"transactionHashes": ["0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542", "0x6d61b62233334ebfb28515b4e2aa0e1011fdf542cf4948ef2831b65f0f1fe542"]}"#;
+        assert_eq!(parse_subscription_request(text, 1), Err("Batch size exceeds the maximum of 1 hashes".to_string()));
+    }
+}